@@ -1,45 +1,36 @@
-use std::sync::Arc;
 use std::time::Duration;
 use dialoguer::{Input, Select};
 use dialoguer::theme::ColorfulTheme;
-use tokio::sync::Mutex;
 use ifconnect::connection::Connection;
+use ifconnect::discovery::{discover_instances, DiscoveryOptions};
+use ifconnect::handle::ConnectionHandle;
 use ifconnect::event_args::{ReceivedDataArgs, ReceivedManifestArgs};
-use ifconnect::{TCP_PORT_V2, UDP_PORT};
+use ifconnect::TCP_PORT_V2;
 
 const UDP_TIMEOUT_SECS: u64 = 30;
 
 #[tokio::main]
 async fn main() {
-    let mut conn = Connection::new();
-
-    let ip = get_device_ip(&mut conn);
-    println!("Connecting to {}:{} via TCP...", ip, TCP_PORT_V2.clone());
-    conn.start_tcp(TCP_PORT_V2.clone(), ip.clone()).await.unwrap();
-    println!("Connected to {}:{}.", ip, TCP_PORT_V2.clone());
+    let ip = loop {
+        match get_device_ip().await {
+            Some(ip) => break ip,
+            None => println!("No IF instance with an IPv4 address was found, make sure Infinite Connect is enabled."),
+        }
+    };
+    println!("Connecting to {}:{} via TCP...", ip, TCP_PORT_V2);
+    let handle = Connection::spawn((ip.as_str(), TCP_PORT_V2 as u16)).await.unwrap();
+    println!("Connected to {}:{}.", ip, TCP_PORT_V2);
 
     // setup event callbacks
-    conn.on_receive_data(Some(on_receive_data));
-    conn.on_receive_manifest(Some(on_receive_manifest));
-
-    // Wrap the connection with Arc<> so we can share it between threads
-    // (in this case, the connection has to go inside the update loop, so we can't just move it there since we won't be able to use it elsewhere.
-    let arc_conn = Arc::new(Mutex::new(conn));
-
-    // Start the update loop to receive/send data from/to the API.
-    let loop_conn = Arc::clone(&arc_conn);
-    tokio::spawn(async move {
-        loop {
-            let mut conn = loop_conn.lock().await;
-            conn.update().await.unwrap();
-        }
-    });
+    handle.on_receive_data(Some(on_receive_data));
+    handle.on_receive_manifest(Some(on_receive_manifest));
 
-    let other_conn = Arc::clone(&arc_conn);
-    display_menu(&other_conn).await;
+    // the handle is cheap to clone and can be moved into other tasks freely
+    display_menu(handle.clone()).await;
 }
 
-fn get_device_ip(conn: &mut Connection) -> String {
+/// Ask for the address of the device, `None` if the search found nothing to connect to.
+async fn get_device_ip() -> Option<String> {
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Do you have the IP address of the device running IF on the local network?")
         .default(0)
@@ -51,12 +42,30 @@ fn get_device_ip(conn: &mut Connection) -> String {
     let result;
 
     if selection == 0 {
-        println!("Running a UDP search with a {}s timeout...", UDP_TIMEOUT_SECS);
-        let instance_information = conn.listen_udp(&UDP_PORT, Some(Duration::from_secs(UDP_TIMEOUT_SECS))).unwrap(); // this will block the current thread
+        println!("Running a UDP search for {}s...", UDP_TIMEOUT_SECS);
+        let options = DiscoveryOptions { window: Some(Duration::from_secs(UDP_TIMEOUT_SECS)), ..DiscoveryOptions::default() };
+        let mut instances = discover_instances(options).await.unwrap();
+        if instances.is_empty() {
+            return None;
+        }
+
+        let instance_information = if instances.len() == 1 {
+            instances.remove(0)
+        } else {
+            let names: Vec<String> = instances.iter().map(|instance| format!("{} ({})", instance.device_name, instance.aircraft)).collect();
+            let instance_selection = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Found several IF instances, please choose one:")
+                .default(0)
+                .items(&names)
+                .interact()
+                .unwrap();
+
+            instances.remove(instance_selection)
+        };
 
         let ipv4_addresses = ifconnect::helpers::get_ipv4_addresses(instance_information.addresses);
         if ipv4_addresses.is_empty() {
-            return String::new();
+            return None;
         }
 
         if ipv4_addresses.len() == 1 {
@@ -80,21 +89,14 @@ fn get_device_ip(conn: &mut Connection) -> String {
         result = ip;
     }
 
-    result
+    Some(result)
 }
 
-async fn display_menu(arc_conn: &Arc<Mutex<Connection>>) {
-    let mut conn = arc_conn.lock().await;
-    conn.get_manifest().await;
-
-    // prevent this conn from blocking the update thread by dropping the mutex lock
-    drop(conn);
+async fn display_menu(handle: ConnectionHandle) {
+    handle.get_manifest().unwrap();
 
-    // loop infinitely to keep the cli app running
-    tokio::spawn(async move {
-        loop {
-        }
-    }).await.unwrap();
+    // keep the cli app running
+    std::future::pending::<()>().await;
 }
 
 fn on_receive_data(args: ReceivedDataArgs) {
@@ -0,0 +1,66 @@
+use std::process::ExitCode;
+use std::time::Duration;
+use ifconnect::connection::Connection;
+use ifconnect::discovery::{discover_instances, DiscoveryOptions};
+use ifconnect::handle::ReconnectOptions;
+use ifconnect::manifest::Manifest;
+use ifconnect::TCP_PORT_V2;
+
+const USAGE: &str = "\
+usage: manifest_diff <stored manifest> [<new manifest> | <device ip>]
+
+Compares a stored manifest against another manifest file, or against the live manifest
+of the device (found over UDP if no ip is given). Exits with 1 if anything was removed,
+re-typed or re-numbered, so it can guard saved ids in CI.";
+const MANIFEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (stored_path, target) = match args.as_slice() {
+        [stored_path] => (stored_path, None),
+        [stored_path, target] => (stored_path, Some(target.as_str())),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2)
+        },
+    };
+
+    let result = async {
+        let stored = Manifest::parse(&std::fs::read_to_string(stored_path)?)?;
+        let new = match target {
+            Some(path) if std::path::Path::new(path).is_file() => Manifest::parse(&std::fs::read_to_string(path)?)?,
+            target => fetch_live_manifest(target).await?,
+        };
+        Ok::<_, Box<dyn std::error::Error>>(stored.diff(&new))
+    }.await;
+
+    match result {
+        Ok(diff) if diff.is_empty() => {
+            println!("the manifests are identical");
+            ExitCode::SUCCESS
+        },
+        Ok(diff) => {
+            print!("{}", diff);
+            if diff.is_breaking() { ExitCode::from(1) } else { ExitCode::SUCCESS }
+        },
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(2)
+        },
+    }
+}
+
+async fn fetch_live_manifest(ip: Option<&str>) -> Result<Manifest, Box<dyn std::error::Error>> {
+    let options = ReconnectOptions { enabled: false, ..ReconnectOptions::default() };
+    let handle = match ip {
+        Some(ip) => Connection::spawn_with_options((ip, TCP_PORT_V2 as u16), options).await?,
+        None => {
+            let instances = discover_instances(DiscoveryOptions::default()).await?;
+            let instance = instances.first().ok_or("no IF instances were found")?;
+            Connection::spawn_instance(instance, options).await?
+        },
+    };
+
+    Ok(handle.fetch_manifest(MANIFEST_TIMEOUT).await?)
+}
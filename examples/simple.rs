@@ -1,61 +1,53 @@
-use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
 use ifconnect::connection::Connection;
-use ifconnect::event_args::{ReceivedDataArgs, ReceivedManifestArgs};
-use ifconnect::{TCP_PORT_V2, UDP_PORT};
+use ifconnect::discovery::{discover_instances, DiscoveryOptions};
+use ifconnect::event_args::{ConnectionStateArgs, ReceivedDataArgs, ReceivedManifestArgs};
+use ifconnect::handle::ReconnectOptions;
 
 #[tokio::main]
 async fn main() {
-    let mut conn = Connection::new();
-    let instance = conn.listen_udp(&UDP_PORT, Some(Duration::from_secs(30))).unwrap(); // this will block the current thread
-
-    let ipv4_addresses = ifconnect::helpers::get_ipv4_addresses(instance.addresses);
-    if ipv4_addresses.is_empty() {
-        println!("no IPv4 addresses were supplied, quitting");
-        return;
-    } // quit if no ipv4 addresses were supplied by the manifest
-
-    // start the tcp connection
-    println!("using {} to connect to IF", ipv4_addresses[0]);
-    conn.start_tcp(TCP_PORT_V2.clone(), ipv4_addresses[0].clone()).await.unwrap();
+    let options = DiscoveryOptions { window: Some(Duration::from_secs(30)), ..DiscoveryOptions::default() };
+    let instances = discover_instances(options).await.unwrap();
+    let instance = match instances.into_iter().next() {
+        Some(instance) => instance,
+        None => {
+            println!("no IF instances were found, quitting");
+            return;
+        },
+    };
+
+    // start the tcp connection, it is driven by background tasks from here on.
+    // if the device changes its address while we are disconnected, it is found again over UDP.
+    println!("connecting to {}", instance.device_name);
+    let options = ReconnectOptions { rediscover: true, ..ReconnectOptions::default() };
+    let handle = Connection::spawn_instance(&instance, options).await.unwrap();
     println!("connected successfully.");
 
     // setup event callbacks
-    conn.on_receive_data(Some(on_receive_data));
-    conn.on_receive_manifest(Some(on_receive_manifest));
-
-    // Wrap the connection with Arc<> so we can share it between threads
-    // (in this case, the connection has to go inside the update loop, so we can't just move it there since we won't be able to use it elsewhere.
-    let arc_conn = Arc::new(Mutex::new(conn));
-
-    // Start the update loop to receive/send data from/to the API.
-    let loop_conn = Arc::clone(&arc_conn);
-    tokio::spawn(async move {
-        loop {
-            let mut conn = loop_conn.lock().await;
-            conn.update().await.unwrap();
-        }
-    });
+    handle.on_receive_data(Some(on_receive_data));
+    handle.on_receive_manifest(Some(on_receive_manifest));
+    handle.on_state_change(Some(on_state_change));
 
-    let other_conn = Arc::clone(&arc_conn);
-    let mut conn = other_conn.lock().await;
-    conn.get_manifest().await;
+    handle.fetch_manifest(Duration::from_secs(30)).await.unwrap();
 
-    // prevent this conn from blocking the update thread by dropping the mutex lock
-    drop(conn);
+    let heading = handle.get_value("aircraft/0/heading_magnetic", Duration::from_secs(5)).await;
+    match heading {
+        Ok(value) => println!("magnetic heading: {}", value),
+        Err(error) => println!("failed to get the heading: {}", error),
+    }
 
-    // loop infinitely to keep the cli app running
-    tokio::spawn(async move {
-        loop {
-        }
-    }).await.unwrap();
+    // keep the app running so the callbacks keep firing
+    std::future::pending::<()>().await;
 }
 
 fn on_receive_data(args: ReceivedDataArgs) {
     println!("on receive data: {} {}", args.command_id, args.data);
 }
 
+fn on_state_change(args: ConnectionStateArgs) {
+    println!("connection state changed: {:?} -> {:?}", args.previous_state, args.state);
+}
+
 fn on_receive_manifest(args: ReceivedManifestArgs) {
     println!("on receive manifest: {} entries", args.manifest.get_number_of_entries());
 
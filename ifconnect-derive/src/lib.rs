@@ -0,0 +1,91 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// Derive `ifconnect::state::IfState` for a struct with named fields.
+///
+/// Every field is annotated with the manifest path it is read from:
+///
+/// ```ignore
+/// #[derive(IfState)]
+/// struct Lights {
+///     #[state(path = "aircraft/0/systems/lights/landing")]
+///     landing: bool,
+///     // fields that are an `Option` may be missing from the manifest
+///     #[state(path = "aircraft/0/systems/lights/strobe")]
+///     strobe: Option<bool>,
+/// }
+/// ```
+#[proc_macro_derive(IfState, attributes(state))]
+pub fn derive_if_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(&input.ident, "IfState can only be derived for structs with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(&input.ident, "IfState can only be derived for structs")),
+    };
+
+    let mut descriptions = Vec::new();
+    let mut initializers = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let path = field_path(field)?;
+        let name = ident.to_string();
+
+        descriptions.push(quote! {
+            ::ifconnect::state::StateField {
+                name: #name,
+                path: #path,
+                data_type: <#ty as ::ifconnect::state::StateValue>::DATA_TYPE,
+                required: <#ty as ::ifconnect::state::StateValue>::REQUIRED,
+            }
+        });
+        initializers.push(quote! {
+            #ident: ::ifconnect::state::StateValue::from_value(values.next().flatten())?
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::ifconnect::state::IfState for #name #type_generics #where_clause {
+            fn fields() -> &'static [::ifconnect::state::StateField] {
+                const FIELDS: &[::ifconnect::state::StateField] = &[#(#descriptions),*];
+                FIELDS
+            }
+
+            fn from_values(values: ::std::vec::Vec<::std::option::Option<::ifconnect::typed_value::TypedValue>>) -> ::std::option::Option<Self> {
+                let mut values = values.into_iter();
+                ::std::option::Option::Some(Self {
+                    #(#initializers),*
+                })
+            }
+        }
+    })
+}
+
+/// Read the path out of the `#[state(path = "...")]` attribute of a field.
+fn field_path(field: &syn::Field) -> syn::Result<LitStr> {
+    let mut path = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("state")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("path") {
+                path = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `path = \"...\"`"))
+            }
+        })?;
+    }
+
+    path.ok_or_else(|| syn::Error::new_spanned(field, "missing `#[state(path = \"...\")]` attribute"))
+}
@@ -0,0 +1,116 @@
+#[cfg(feature = "async")]
+use crate::manifest::Manifest;
+use crate::typed_value::TypedValue;
+
+// the API sends angles in radians and speeds in metres per second
+const KNOTS_PER_METRE_PER_SECOND: f64 = 1.943_844_5;
+const FEET_PER_MINUTE_PER_METRE_PER_SECOND: f64 = 196.850_394;
+
+const NAME: &str = "aircraft/0/name";
+const LATITUDE: &str = "aircraft/0/latitude";
+const LONGITUDE: &str = "aircraft/0/longitude";
+const ALTITUDE_MSL: &str = "aircraft/0/altitude_msl";
+const ALTITUDE_AGL: &str = "aircraft/0/altitude_agl";
+const HEADING_MAGNETIC: &str = "aircraft/0/heading_magnetic";
+const HEADING_TRUE: &str = "aircraft/0/heading_true";
+const PITCH: &str = "aircraft/0/pitch";
+const BANK: &str = "aircraft/0/bank";
+const INDICATED_AIRSPEED: &str = "aircraft/0/indicated_airspeed";
+const TRUE_AIRSPEED: &str = "aircraft/0/true_airspeed";
+const GROUNDSPEED: &str = "aircraft/0/groundspeed";
+const VERTICAL_SPEED: &str = "aircraft/0/vertical_speed";
+const ON_GROUND: &str = "aircraft/0/is_on_ground";
+const FLAPS: &str = "aircraft/0/systems/flaps/state";
+const GEAR: &str = "aircraft/0/systems/landing_gear/lever_state";
+
+const PATHS: &[&str] = &[
+    NAME, LATITUDE, LONGITUDE, ALTITUDE_MSL, ALTITUDE_AGL, HEADING_MAGNETIC, HEADING_TRUE, PITCH, BANK,
+    INDICATED_AIRSPEED, TRUE_AIRSPEED, GROUNDSPEED, VERTICAL_SPEED, ON_GROUND, FLAPS, GEAR,
+];
+
+/// A snapshot of the position, attitude and speeds of the user aircraft.
+/// Fields are `None` when the aircraft doesn't have the state in its manifest.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AircraftState {
+    pub name: Option<String>,
+    /// Degrees.
+    pub latitude: Option<f64>,
+    /// Degrees.
+    pub longitude: Option<f64>,
+    /// Feet.
+    pub altitude_msl: Option<f64>,
+    /// Feet.
+    pub altitude_agl: Option<f64>,
+    /// Degrees.
+    pub heading_magnetic: Option<f64>,
+    /// Degrees.
+    pub heading_true: Option<f64>,
+    /// Degrees, positive nose up.
+    pub pitch: Option<f64>,
+    /// Degrees.
+    pub bank: Option<f64>,
+    /// Knots.
+    pub indicated_airspeed: Option<f64>,
+    /// Knots.
+    pub true_airspeed: Option<f64>,
+    /// Knots.
+    pub groundspeed: Option<f64>,
+    /// Feet per minute.
+    pub vertical_speed: Option<f64>,
+    pub on_ground: Option<bool>,
+    /// Index of the flaps setting, 0 being retracted.
+    pub flaps: Option<i32>,
+    /// State of the gear lever as sent by the API.
+    pub gear: Option<i32>,
+}
+
+impl AircraftState {
+    /// The manifest paths the snapshot is read from.
+    pub fn paths() -> &'static [&'static str] {
+        PATHS
+    }
+
+    /// Fill in the field read from this path, converting the value to the unit of the field.
+    /// Values of another path or of an unexpected type are ignored.
+    pub fn apply(&mut self, path: &str, value: &TypedValue) {
+        let number = value.as_f64();
+        match path {
+            NAME => if let TypedValue::String(name) = value { self.name = Some(name.clone()) },
+            LATITUDE => self.latitude = number,
+            LONGITUDE => self.longitude = number,
+            ALTITUDE_MSL => self.altitude_msl = number,
+            ALTITUDE_AGL => self.altitude_agl = number,
+            HEADING_MAGNETIC => self.heading_magnetic = number.map(f64::to_degrees),
+            HEADING_TRUE => self.heading_true = number.map(f64::to_degrees),
+            PITCH => self.pitch = number.map(f64::to_degrees),
+            BANK => self.bank = number.map(f64::to_degrees),
+            INDICATED_AIRSPEED => self.indicated_airspeed = number.map(|speed| speed * KNOTS_PER_METRE_PER_SECOND),
+            TRUE_AIRSPEED => self.true_airspeed = number.map(|speed| speed * KNOTS_PER_METRE_PER_SECOND),
+            GROUNDSPEED => self.groundspeed = number.map(|speed| speed * KNOTS_PER_METRE_PER_SECOND),
+            VERTICAL_SPEED => self.vertical_speed = number.map(|speed| speed * FEET_PER_MINUTE_PER_METRE_PER_SECOND),
+            ON_GROUND => if let TypedValue::Boolean(on_ground) = value { self.on_ground = Some(*on_ground) },
+            FLAPS => if let TypedValue::Integer32(flaps) = value { self.flaps = Some(*flaps) },
+            GEAR => if let TypedValue::Integer32(gear) = value { self.gear = Some(*gear) },
+            _ => {},
+        }
+    }
+
+    /// The ids of the snapshot paths that are in the manifest.
+    #[cfg(feature = "async")]
+    pub(crate) fn state_ids(manifest: &Manifest) -> Vec<(&'static str, i32)> {
+        PATHS.iter()
+            .filter_map(|path| manifest.get_entry_by_path(path).ok().map(|entry| (*path, entry.id)))
+            .collect()
+    }
+
+    /// Build a snapshot from the values of the paths returned by [`AircraftState::state_ids`].
+    #[cfg(feature = "async")]
+    pub(crate) fn from_values(paths: &[&str], values: &[TypedValue]) -> Self {
+        let mut state = Self::default();
+        for (path, value) in paths.iter().zip(values) {
+            state.apply(path, value);
+        }
+
+        state
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use crate::codec::{encode_get_request, encode_run_request, encode_set_request, FrameDecoder, MANIFEST_ID};
+use crate::error::{Error, FrameError};
+use crate::manifest::Manifest;
+use crate::typed_value::TypedValue;
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Synchronous connection to the API over a std TCP socket, for programs without an async runtime.
+///
+/// Every call blocks until it is done: getting a value sends the request and reads until its
+/// response arrives. Responses left over from requests that timed out are skipped.
+pub struct Connection {
+    stream: TcpStream,
+    decoder: FrameDecoder,
+    manifest: Option<Manifest>,
+    // how many responses per id are still due for requests that timed out
+    abandoned: HashMap<i32, usize>,
+    // whether set values may be converted to the type of the state
+    coerce_values: bool,
+}
+
+impl Connection {
+    /// Connect to the API, e.g. `Connection::connect(("192.168.1.20", TCP_PORT_V2 as u16))`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    /// Use an already connected socket.
+    pub fn from_stream(stream: TcpStream) -> Result<Self, Error> {
+        // requests are tiny, don't hold them back
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream,
+            decoder: FrameDecoder::new(),
+            manifest: None,
+            abandoned: HashMap::new(),
+            coerce_values: false,
+        })
+    }
+
+    /// Request the manifest and wait for it. Paths can't be resolved before it is fetched.
+    pub fn fetch_manifest(&mut self, timeout: Duration) -> Result<Manifest, Error> {
+        self.get_value_id(MANIFEST_ID, timeout)?;
+        self.manifest().cloned()
+    }
+
+    /// The last received manifest.
+    pub fn manifest(&self) -> Result<&Manifest, Error> {
+        self.manifest.as_ref().ok_or(Error::NoManifest())
+    }
+
+    pub fn get_value(&mut self, state_path: &str, timeout: Duration) -> Result<TypedValue, Error> {
+        let state_id = self.resolve_path(state_path)?;
+        self.get_value_id(state_id, timeout)
+    }
+
+    pub fn get_value_id(&mut self, state_id: i32, timeout: Duration) -> Result<TypedValue, Error> {
+        self.stream.write_all(&encode_get_request(state_id))?;
+        self.await_response(state_id, timeout)
+    }
+
+    pub fn set(&mut self, state_path: &str, value: TypedValue) -> Result<(), Error> {
+        let state_id = self.resolve_path(state_path)?;
+        self.set_id(state_id, value)
+    }
+
+    /// Set a state. The value must match the type of the state in the manifest,
+    /// unless value coercion is enabled and it can be converted without loss.
+    pub fn set_id(&mut self, state_id: i32, value: TypedValue) -> Result<(), Error> {
+        let value = self.manifest()?.check_set_value(state_id, value, self.coerce_values)?;
+        Ok(self.stream.write_all(&encode_set_request(state_id, &value))?)
+    }
+
+    /// Allow `set` to convert values to the type of the state (i32 to i64 or f64, f32 to f64, bool to i32).
+    /// Disabled by default.
+    pub fn set_value_coercion(&mut self, enabled: bool) {
+        self.coerce_values = enabled;
+    }
+
+    pub fn run(&mut self, command_path: &str) -> Result<(), Error> {
+        let command_id = self.resolve_path(command_path)?;
+        self.run_id(command_id)
+    }
+
+    pub fn run_id(&mut self, command_id: i32) -> Result<(), Error> {
+        Ok(self.stream.write_all(&encode_run_request(command_id))?)
+    }
+
+    fn resolve_path(&self, path: &str) -> Result<i32, Error> {
+        Ok(self.manifest()?.get_entry_by_path(path)?.id)
+    }
+
+    /// Read until the response for the id arrives, handling any manifest received on the way.
+    /// The API answers requests in order, so the responses to requests that timed out come first.
+    fn await_response(&mut self, state_id: i32, timeout: Duration) -> Result<TypedValue, Error> {
+        // timeouts too long to represent never expire
+        let deadline = Instant::now().checked_add(timeout);
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+
+        loop {
+            match self.decoder.next_value(self.manifest.as_ref()) {
+                Ok(Some((id, value))) => {
+                    if let (MANIFEST_ID, TypedValue::String(manifest_str)) = (id, &value) {
+                        self.manifest = Some(Manifest::parse(manifest_str)?);
+                    }
+                    if self.response_abandoned(id) {
+                        continue
+                    }
+                    if id == state_id {
+                        return Ok(value)
+                    }
+                    continue
+                },
+                Ok(None) => {},
+                // the frame has been consumed, so the stream is still in sync
+                Err(Error::Frame(FrameError::UnknownType(id))) => {
+                    self.response_abandoned(id);
+                    continue
+                },
+                Err(error) => return Err(error),
+            }
+
+            let read_timeout = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(self.timed_out(state_id))
+                    }
+                    Some(remaining)
+                },
+                None => None,
+            };
+            self.stream.set_read_timeout(read_timeout)?;
+
+            match self.stream.read(&mut buf) {
+                // no bytes means the API closed the connection
+                Ok(0) => return Err(Error::Disconnected()),
+                Ok(len) => self.decoder.extend(&buf[0..len]),
+                Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Err(self.timed_out(state_id)),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Remember to skip the response to a request that timed out, whenever it arrives.
+    fn timed_out(&mut self, state_id: i32) -> Error {
+        *self.abandoned.entry(state_id).or_insert(0) += 1;
+        Error::Timeout(state_id)
+    }
+
+    /// Whether a response is the late answer to a request that timed out, and should be skipped.
+    fn response_abandoned(&mut self, id: i32) -> bool {
+        match self.abandoned.get_mut(&id) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.abandoned.remove(&id);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use crate::connection::ConnectionState;
+use crate::error::{CaptureError, Error};
+use crate::handle::ConnectionHandle;
+
+/// Every capture file starts with these bytes, followed by the format version.
+const CAPTURE_MAGIC: &[u8; 4] = b"IFCC";
+const CAPTURE_VERSION: u8 = 1;
+// replays slowed down beyond this wait no longer for the next record
+const MAX_REPLAY_DELAY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Which way the bytes of a record went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the API.
+    Received,
+    /// Sent to the API, one record per request.
+    Sent,
+}
+
+impl Direction {
+    fn to_byte(self) -> u8 {
+        match self {
+            Direction::Received => 0,
+            Direction::Sent => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Direction::Received),
+            1 => Some(Direction::Sent),
+            _ => None,
+        }
+    }
+}
+
+/// Bytes seen on the connection, with the time since the recording started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    pub timestamp: Duration,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// Writes the bytes going over a connection to a capture file.
+///
+/// The file starts with `IFCC` and a version byte. Each record follows as the direction (1 byte),
+/// the timestamp in microseconds (`u64`), the length (`u32`) and the bytes, all little-endian.
+/// Timestamps are taken from a monotonic clock, so they never go backwards.
+pub struct Recorder {
+    writer: Box<dyn Write + Send>,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Record to a new file, replacing it if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::create(path)?;
+        Self::new(BufWriter::new(file))
+    }
+
+    /// Record to any writer. The header is written right away.
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> Result<Self, Error> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        writer.write_all(&[CAPTURE_VERSION])?;
+
+        Ok(Self {
+            writer: Box::new(writer),
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        let timestamp = self.started.elapsed().as_micros() as u64;
+
+        self.writer.write_all(&[direction.to_byte()])?;
+        self.writer.write_all(&timestamp.to_le_bytes())?;
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(bytes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// A recorded session, read back from a capture file.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    records: Vec<CaptureRecord>,
+}
+
+impl Capture {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        Self::read_from(BufReader::new(file))
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header).map_err(|_| CaptureError::InvalidHeader)?;
+        if &header[0..4] != CAPTURE_MAGIC {
+            return Err(CaptureError::InvalidHeader.into())
+        }
+        if header[4] != CAPTURE_VERSION {
+            return Err(CaptureError::UnsupportedVersion(header[4]).into())
+        }
+
+        let mut records = Vec::new();
+        loop {
+            let index = records.len();
+            let mut direction = [0u8; 1];
+            // the file may only end between records
+            if reader.read(&mut direction)? == 0 {
+                break
+            }
+            let direction = Direction::from_byte(direction[0]).ok_or(CaptureError::InvalidDirection(index, direction[0]))?;
+
+            let mut record_header = [0u8; 12];
+            read_record_part(&mut reader, &mut record_header, index)?;
+            let timestamp = u64::from_le_bytes(record_header[0..8].try_into().unwrap());
+            let length = u32::from_le_bytes(record_header[8..12].try_into().unwrap());
+
+            // the buffer grows with the bytes actually read, a corrupted length can't allocate more
+            let mut bytes = Vec::new();
+            if (&mut reader).take(length as u64).read_to_end(&mut bytes)? < length as usize {
+                return Err(CaptureError::Truncated(index).into())
+            }
+
+            records.push(CaptureRecord {
+                timestamp: Duration::from_micros(timestamp),
+                direction,
+                bytes,
+            });
+        }
+
+        Ok(Self { records })
+    }
+
+    pub fn from_records(records: Vec<CaptureRecord>) -> Self {
+        Self { records }
+    }
+
+    pub fn records(&self) -> &[CaptureRecord] {
+        &self.records
+    }
+
+    /// How long the recorded session lasted.
+    pub fn duration(&self) -> Duration {
+        self.records.last().map_or(Duration::ZERO, |record| record.timestamp)
+    }
+}
+
+fn read_record_part<R: Read>(reader: &mut R, buf: &mut [u8], index: usize) -> Result<(), Error> {
+    reader.read_exact(buf).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => CaptureError::Truncated(index).into(),
+        _ => Error::Io(error),
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// How much faster than recorded to replay; 1.0 is real time.
+    /// `f64::INFINITY`, and any speed that isn't a positive number, replays everything without waiting.
+    pub speed: f64,
+}
+
+impl ReplayOptions {
+    /// How long after the start of the replay a record is due, `None` if it is due right away.
+    fn delay(&self, timestamp: Duration) -> Option<Duration> {
+        if !(self.speed.is_finite() && self.speed > 0.0) {
+            return None
+        }
+        let delay = Duration::try_from_secs_f64(timestamp.as_secs_f64() / self.speed).unwrap_or(MAX_REPLAY_DELAY);
+        Some(delay.min(MAX_REPLAY_DELAY))
+    }
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self { speed: 1.0 }
+    }
+}
+
+/// Feeds a capture into a connection instead of the API.
+/// Created with [`Connection::replay`](crate::connection::Connection::replay).
+///
+/// The received bytes are handled at the time they were recorded, so the callbacks and
+/// subscriptions of the handle see the same values in the same order as the recorded session.
+/// The recorded requests are replayed too, so responses are matched to them like they were
+/// originally. Requests made on the handle during the replay are dropped.
+pub struct Replay {
+    handle: ConnectionHandle,
+    requests: mpsc::UnboundedReceiver<Vec<u8>>,
+    capture: Capture,
+    options: ReplayOptions,
+}
+
+impl Replay {
+    pub(crate) fn new(capture: Capture, options: ReplayOptions) -> Self {
+        let (handle, requests) = ConnectionHandle::detached();
+        Self { handle, requests, capture, options }
+    }
+
+    /// The handle the capture is replayed into. Register callbacks before calling [`Replay::run`].
+    pub fn handle(&self) -> &ConnectionHandle {
+        &self.handle
+    }
+
+    /// Replay the whole capture. Returns once it has been replayed or an error in the received
+    /// bytes ended the recorded session; the connection is disconnected afterwards.
+    pub async fn run(mut self) -> Result<(), Error> {
+        self.handle.set_state(ConnectionState::Connected);
+        let result = self.replay_records().await;
+        self.handle.set_state(ConnectionState::Disconnected);
+
+        result
+    }
+
+    async fn replay_records(&mut self) -> Result<(), Error> {
+        let started = tokio::time::Instant::now();
+        for record in self.capture.records.iter() {
+            match self.options.delay(record.timestamp) {
+                Some(delay) => loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(started + delay) => break,
+                        // only the recorded requests are answered
+                        request = self.requests.recv() => if request.is_none() { break },
+                    }
+                },
+                None => while self.requests.try_recv().is_ok() {},
+            }
+
+            self.handle.with_data(|data| match record.direction {
+                Direction::Received => data.receive_bytes(&record.bytes),
+                Direction::Sent => {
+                    data.request_replayed(&record.bytes);
+                    Ok(())
+                },
+            })?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::convert::TryInto;
+use crate::error::{Error, FrameError};
+use crate::manifest::Manifest;
+use crate::typed_value::{Type, TypedValue};
+
+/// Id used by the API for the manifest request/response.
+pub const MANIFEST_ID: i32 = -1;
+
+/// Upper bound on a single frame payload, guards against allocating on a corrupted length.
+pub const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+const HEADER_LENGTH: usize = 8;
+
+/// A complete response frame: the id followed by its raw payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub id: i32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Decode the payload of this frame as a value of the given type.
+    pub fn decode(&self, data_type: &Type) -> Result<TypedValue, Error> {
+        decode_value(self.id, data_type, &self.payload)
+    }
+}
+
+/// Buffered decoder for the responses sent by the API.
+///
+/// Every response is laid out as `id: i32`, `length: i32` and `length` bytes of payload
+/// (all little-endian). String payloads additionally start with their own `i32` length.
+/// Bytes can be fed in arbitrary pieces; frames are only emitted once they are complete.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append received bytes to the internal buffer.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes buffered but not yet emitted as a frame.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Drop any partially received frame.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Take the next complete frame out of the buffer, if there is one.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, Error> {
+        if self.buffer.len() < HEADER_LENGTH {
+            return Ok(None)
+        }
+
+        let id = read_le_i32(&self.buffer[0..4]);
+        let length = read_le_i32(&self.buffer[4..8]);
+        if length < 0 {
+            return Err(FrameError::NegativeLength(id, length).into())
+        }
+
+        let length = length as usize;
+        if length > MAX_FRAME_LENGTH {
+            return Err(FrameError::TooLarge(id, length).into())
+        }
+        if self.buffer.len() < HEADER_LENGTH + length {
+            return Ok(None)
+        }
+
+        let payload = self.buffer[HEADER_LENGTH..HEADER_LENGTH + length].to_vec();
+        self.buffer.drain(0..HEADER_LENGTH + length);
+
+        Ok(Some(Frame { id, payload }))
+    }
+
+    /// Take the next complete frame and decode it using the types from the manifest.
+    /// The manifest response (id -1) is always decoded as a string.
+    ///
+    /// A frame that cannot be decoded is still consumed, so the decoder stays in sync.
+    pub fn next_value(&mut self, manifest: Option<&Manifest>) -> Result<Option<(i32, TypedValue)>, Error> {
+        let frame = match self.next_frame()? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        let data_type = if frame.id == MANIFEST_ID {
+            Type::String
+        } else {
+            match manifest.map(|manifest| manifest.get_data_type_for_id(&frame.id)) {
+                Some(Ok(data_type)) => data_type,
+                _ => return Err(FrameError::UnknownType(frame.id).into()),
+            }
+        };
+
+        Ok(Some((frame.id, frame.decode(&data_type)?)))
+    }
+}
+
+/// Decode a response payload as a value of the given type.
+pub fn decode_value(id: i32, data_type: &Type, payload: &[u8]) -> Result<TypedValue, Error> {
+    let expect_len = |expected: usize| {
+        if payload.len() == expected {
+            Ok(())
+        } else {
+            Err(FrameError::PayloadLength(id, expected, payload.len()))
+        }
+    };
+
+    let value = match data_type {
+        Type::Boolean => {
+            expect_len(1)?;
+            TypedValue::Boolean(payload[0] != 0)
+        },
+        Type::Integer32 => {
+            expect_len(4)?;
+            TypedValue::Integer32(read_le_i32(payload))
+        },
+        Type::Float => {
+            expect_len(4)?;
+            TypedValue::Float(f32::from_le_bytes(payload.try_into().unwrap()))
+        },
+        Type::Double => {
+            expect_len(8)?;
+            TypedValue::Double(f64::from_le_bytes(payload.try_into().unwrap()))
+        },
+        Type::Long => {
+            expect_len(8)?;
+            TypedValue::Long(i64::from_le_bytes(payload.try_into().unwrap()))
+        },
+        Type::String => {
+            if payload.len() < 4 {
+                return Err(FrameError::PayloadLength(id, 4, payload.len()).into())
+            }
+            let string_len = read_le_i32(&payload[0..4]);
+            if string_len < 0 || string_len as usize != payload.len() - 4 {
+                return Err(FrameError::StringLength(id, string_len, payload.len() - 4).into())
+            }
+            let string = String::from_utf8(payload[4..].to_vec()).map_err(|_| FrameError::InvalidUtf8(id))?;
+            TypedValue::String(string)
+        },
+    };
+
+    Ok(value)
+}
+
+/// Encode a get state request. Also used for the manifest request (id -1).
+pub fn encode_get_request(state_id: i32) -> Vec<u8> {
+    encode_request_header(state_id, false)
+}
+
+/// Encode a set state request with the given value.
+pub fn encode_set_request(state_id: i32, value: &TypedValue) -> Vec<u8> {
+    let mut bytes = encode_request_header(state_id, true);
+    bytes.extend(value.to_bytes_vec());
+    bytes
+}
+
+/// Encode a run command request.
+pub fn encode_run_request(command_id: i32) -> Vec<u8> {
+    encode_request_header(command_id, false)
+}
+
+/// Encode a response the way the API sends it, e.g. for test fixtures.
+/// Values are laid out like in set requests, prefixed with the id and the payload length,
+/// except for booleans which the API answers with a single byte.
+pub fn encode_response(id: i32, value: &TypedValue) -> Vec<u8> {
+    let payload = match value {
+        TypedValue::Boolean(val) => vec![*val as u8],
+        value => value.to_bytes_vec(),
+    };
+    let mut bytes = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    bytes.extend(id.to_le_bytes());
+    bytes.extend((payload.len() as i32).to_le_bytes());
+    bytes.extend(payload);
+    bytes
+}
+
+// the get/set flag is sent as a little-endian i32, like the original client did
+fn encode_request_header(id: i32, is_set: bool) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8);
+    bytes.extend(id.to_le_bytes());
+    bytes.extend((is_set as i32).to_le_bytes());
+    bytes
+}
+
+fn read_le_i32(bytes: &[u8]) -> i32 {
+    i32::from_le_bytes(bytes[0..4].try_into().unwrap())
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use crate::error::Error;
+#[cfg(feature = "async")]
+use crate::handle::ConnectionHandle;
+use crate::manifest::Manifest;
+
+const COMMANDS_PREFIX: &str = "commands/";
+
+/// A command listed in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub id: i32,
+    pub path: String,
+}
+
+impl Command {
+    /// The path without the `commands/` prefix, e.g. `FlapsDown`.
+    pub fn name(&self) -> &str {
+        self.path.strip_prefix(COMMANDS_PREFIX).unwrap_or(&self.path)
+    }
+
+    #[cfg(feature = "async")]
+    pub fn run(&self, handle: &ConnectionHandle) -> Result<(), Error> {
+        handle.run_id(self.id)
+    }
+}
+
+/// Every command in a manifest, sorted by path.
+#[derive(Debug, Clone, Default)]
+pub struct Commands {
+    commands: Vec<Command>,
+}
+
+impl Commands {
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        let mut commands: Vec<Command> = manifest.get_entries().iter()
+            .filter(|entry| entry.is_command())
+            .map(|entry| Command { id: entry.id, path: entry.string.clone() })
+            .collect();
+        commands.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Self { commands }
+    }
+
+    /// Look a command up by its name or full path.
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| command.name() == name || command.path == name)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Command> {
+        self.commands.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a Commands {
+    type Item = &'a Command;
+    type IntoIter = std::slice::Iter<'a, Command>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.commands.iter()
+    }
+}
+
+/// Generate Rust source with a `&str` constant for the path of every command in the manifest,
+/// so a misspelled command fails to compile instead of failing with `NoSuchEntryPath`.
+///
+/// Paths are used rather than ids, as ids change between versions of Infinite Flight.
+/// `commands/FlapsDown` becomes `pub const FLAPS_DOWN: &str = "commands/FlapsDown";`.
+/// Fails with [`Error::ConstantCollision`] if two paths only differ in punctuation or case,
+/// as they would get the same constant name.
+pub fn generate_command_constants(manifest: &Manifest) -> Result<String, Error> {
+    let mut source = String::from("// Generated by ifconnect from a captured manifest, do not edit.\n");
+    let mut paths: HashMap<String, &str> = HashMap::new();
+    let commands = Commands::from_manifest(manifest);
+
+    for command in commands.iter() {
+        let name = constant_name(command.name());
+        if let Some(other_path) = paths.insert(name.clone(), &command.path) {
+            return Err(Error::ConstantCollision(name, other_path.to_string(), command.path.clone()))
+        }
+        writeln!(source, "pub const {}: &str = {:?};", name, command.path).unwrap();
+    }
+
+    Ok(source)
+}
+
+/// Read a manifest captured to a file and write the command constants for it, e.g. from a build script:
+///
+/// ```ignore
+/// // build.rs
+/// let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("commands.rs");
+/// ifconnect::commands::write_command_constants("manifest.txt", &out).unwrap();
+///
+/// // src/main.rs
+/// mod commands { include!(concat!(env!("OUT_DIR"), "/commands.rs")); }
+/// handle.run(commands::FLAPS_DOWN)?;
+/// ```
+pub fn write_command_constants<P: AsRef<Path>, Q: AsRef<Path>>(manifest_path: P, out_path: Q) -> Result<(), Error> {
+    let manifest = Manifest::parse(&std::fs::read_to_string(manifest_path)?)?;
+    std::fs::write(out_path, generate_command_constants(&manifest)?)?;
+    Ok(())
+}
+
+/// Turn a command name like `Autopilot.ToggleHDG` into `AUTOPILOT_TOGGLE_HDG`.
+fn constant_name(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut constant = String::new();
+    for (index, c) in chars.iter().enumerate() {
+        if !c.is_ascii_alphanumeric() {
+            if !constant.is_empty() && !constant.ends_with('_') {
+                constant.push('_');
+            }
+            continue
+        }
+
+        // start a new word on a lower to upper case change, or at the last capital of an acronym
+        let previous = index.checked_sub(1).map(|index| chars[index]);
+        let next = chars.get(index + 1);
+        let word_start = c.is_ascii_uppercase() && previous.is_some_and(|previous| {
+            previous.is_ascii_lowercase() || previous.is_ascii_digit()
+                || (previous.is_ascii_uppercase() && next.is_some_and(|next| next.is_ascii_lowercase()))
+        });
+        if word_start && !constant.ends_with('_') {
+            constant.push('_');
+        }
+        constant.push(c.to_ascii_uppercase());
+    }
+
+    let constant = constant.trim_end_matches('_').to_string();
+    match constant.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => constant,
+        _ => format!("COMMAND_{}", constant),
+    }
+}
@@ -1,71 +1,71 @@
-use std::error::Error;
-use std::net::{UdpSocket};
-use std::sync::Arc;
+use std::future::Future;
+use std::net::SocketAddr;
+#[cfg(feature = "discovery")]
+use std::net::UdpSocket;
 use std::time::{Duration, Instant};
-use serde;
-use serde::{Deserialize};
-use tokio::io::Interest;
-use tokio::net::TcpStream;
-use tokio::sync::{Mutex};
-use crate::data::ConnectionData;
-use crate::error::ManifestError;
-use crate::event_args::{ReceivedDataArgs, ReceivedManifestArgs};
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+use tokio::sync::{oneshot, watch};
+use crate::aircraft_state::AircraftState;
+use crate::capture::{Capture, Recorder, Replay, ReplayOptions};
+use crate::commands::Commands;
+use crate::data::{await_values, ConnectionData};
+#[cfg(feature = "discovery")]
+pub use crate::discovery::InstanceInformation;
+#[cfg(feature = "discovery")]
+use crate::discovery::parse_instance_information;
+use crate::error::Error;
+#[cfg(feature = "discovery")]
+use crate::error::DiscoveryError;
+use crate::handle::{ConnectionHandle, ReconnectOptions};
+#[cfg(feature = "discovery")]
+use crate::manifest_cache::{ManifestCache, ManifestCacheKey};
+use crate::state::{IfState, StateBinding};
+#[cfg(feature = "discovery")]
+use crate::helpers::get_ipv4_addresses;
+#[cfg(feature = "discovery")]
+use crate::TCP_PORT_V2;
+use crate::event_args::{ConnectionStateArgs, ReceivedDataArgs, ReceivedManifestArgs};
+use crate::subscription::Subscription;
+use crate::transport::{Connector, NoReconnect, TcpConnector, Transport};
 use crate::typed_value::TypedValue;
 
+#[cfg(feature = "discovery")]
 const UDP_DISCOVERY_ADDRESS: &str = "0.0.0.0";
-const DEFAULT_POLLING_INTERVAL: u32 = 100; // ms
-const DEFAULT_POLLING_STATE: bool = false;
+// how long `update()` waits for data when there is nothing to send
+const UPDATE_READ_TIMEOUT: u64 = 10; // ms
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionState {
     Connected,
     Connecting,
     Disconnected,
 }
 
-#[derive(Deserialize, Clone)]
-#[serde(rename_all = "PascalCase")]
-pub struct InstanceInformation {
-    pub state: String,
-    pub port: u32,
-    #[serde(rename = "DeviceID")]
-    pub device_id: String,
-    pub aircraft: String,
-    pub version: String,
-    pub device_name: String,
-    pub addresses: Vec<String>,
-    pub livery: String
-}
-
-pub struct Connection {
-    state: ConnectionState,
+/// Connection to the API driven by calling `update()` in a loop, over TCP unless
+/// created with [`Connection::with_transport`].
+pub struct Connection<S: Transport = TcpStream> {
+    state: watch::Sender<ConnectionState>,
+    #[cfg(feature = "discovery")]
     connected_instance: Option<InstanceInformation>,
     data: ConnectionData,
 
     // network stuff
+    #[cfg(feature = "discovery")]
     udp_sock: Option<UdpSocket>,
-    tcp_stream: Option<Arc<Mutex<TcpStream>>>,
-
-    // polling
-    enable_polling: bool,
-    states_to_poll: Vec<String>,
-    poll_interval: u32,
-    last_poll: Instant,
+    transport: Option<S>,
 }
 
-impl Default for Connection {
+impl<S: Transport> Default for Connection<S> {
     fn default() -> Self {
         Self {
-            state: ConnectionState::Disconnected,
+            state: watch::Sender::new(ConnectionState::Disconnected),
+            #[cfg(feature = "discovery")]
             connected_instance: None,
             data: ConnectionData::new(),
 
+            #[cfg(feature = "discovery")]
             udp_sock: None,
-            tcp_stream: None,
-
-            enable_polling: DEFAULT_POLLING_STATE,
-            states_to_poll: Vec::new(),
-            poll_interval: DEFAULT_POLLING_INTERVAL,
-            last_poll: Instant::now(),
+            transport: None,
         }
     }
 }
@@ -77,26 +77,18 @@ impl Connection {
     }
 
     /// Discover IF instances over UDP.
-    pub fn listen_udp(&mut self, udp_port: &u32, timeout_dur: Option<Duration>) -> Result<InstanceInformation, ()> {
+    #[cfg(feature = "discovery")]
+    #[deprecated(note = "use `discovery::discover` or `discovery::discover_instances` instead")]
+    pub fn listen_udp(&mut self, udp_port: &u32, timeout_dur: Option<Duration>) -> Result<InstanceInformation, Error> {
         let addr: String = format!("{}:{}", UDP_DISCOVERY_ADDRESS, udp_port);
-        let udp_sock = UdpSocket::bind(&addr).expect("failed to bind udp socket");
+        let udp_sock = UdpSocket::bind(&addr).map_err(DiscoveryError::Bind)?;
 
-        udp_sock.set_read_timeout(timeout_dur).expect("failed to set read timeout on udp socket");
+        udp_sock.set_read_timeout(timeout_dur).map_err(DiscoveryError::Bind)?;
 
         // this will block the thread for the length of timeout_dur, and fail if not received any data
         let mut buf = [0u8; 500];
-        let mut string_result: &str = "";
-        match udp_sock.recv(&mut buf) {
-            Ok(_received) => {
-                string_result = std::str::from_utf8(&buf).expect("failed to parse received udp message into string");
-            },
-            Err(err) => eprintln!("udp receive failed: {}", err)
-        }
-        if string_result.is_empty() { return Err(()) }
-
-        // trim null characters and parse the json string
-        string_result = string_result.trim_matches(char::from(0));
-        let parsed_instance: InstanceInformation = serde_json::from_str(string_result).expect("failed to parse received udp message into json");
+        let received = udp_sock.recv(&mut buf).map_err(DiscoveryError::Receive)?;
+        let parsed_instance = parse_instance_information(&buf[0..received])?;
 
         // keep the socket for reuse
         self.udp_sock = Some(udp_sock);
@@ -105,55 +97,139 @@ impl Connection {
         Ok(parsed_instance)
     }
 
-    pub async fn start_tcp(&mut self, tcp_port: u32, tcp_address: String) -> Result<(), Box<dyn Error>> {
+    /// Connect to the API and drive the connection from background tasks.
+    /// Returns a cloneable handle used to talk to the API; no `update()` loop is needed.
+    ///
+    /// The connection is re-established with the default [`ReconnectOptions`] if it drops.
+    pub async fn spawn<A: ToSocketAddrs>(addr: A) -> Result<ConnectionHandle, Error> {
+        Self::spawn_with_options(addr, ReconnectOptions::default()).await
+    }
+
+    /// Same as [`Connection::spawn`], with custom reconnection behaviour.
+    pub async fn spawn_with_options<A: ToSocketAddrs>(addr: A, options: ReconnectOptions) -> Result<ConnectionHandle, Error> {
+        let addresses: Vec<SocketAddr> = lookup_host(addr).await?.collect();
+        let stream = TcpStream::connect(&addresses[..]).await?;
+        Ok(ConnectionHandle::start(stream, TcpConnector::new(addresses), options))
+    }
+
+    /// Connect to an instance found with discovery, using the first of its IPv4 addresses that accepts
+    /// the connection. With [`ReconnectOptions::rediscover`] set, the instance is looked up again by its
+    /// device id when reconnecting, in case its address changed.
+    #[cfg(feature = "discovery")]
+    pub async fn spawn_instance(instance: &InstanceInformation, options: ReconnectOptions) -> Result<ConnectionHandle, Error> {
+        let addresses: Vec<SocketAddr> = get_ipv4_addresses(instance.addresses.clone()).iter()
+            .filter_map(|ip| format!("{}:{}", ip, TCP_PORT_V2).parse().ok())
+            .collect();
+        let stream = TcpStream::connect(&addresses[..]).await?;
+        let mut connector = TcpConnector::new(addresses);
+        if options.rediscover {
+            connector = connector.rediscovering(instance.device_id.clone(), options.rediscovery_window);
+        }
+        Ok(ConnectionHandle::start(stream, connector, options))
+    }
+
+    /// Drive an already open transport from background tasks, e.g. one half of an in-memory
+    /// [`duplex`](crate::transport::duplex). The connection isn't re-established if it drops.
+    pub fn spawn_transport<T: Transport>(transport: T) -> ConnectionHandle {
+        let options = ReconnectOptions { enabled: false, ..ReconnectOptions::default() };
+        ConnectionHandle::start(transport, NoReconnect::new(), options)
+    }
+
+    /// Same as [`Connection::spawn_transport`], opening a new transport with the connector to reconnect.
+    pub fn spawn_transport_with<C: Connector>(transport: C::Transport, connector: C, options: ReconnectOptions) -> ConnectionHandle {
+        ConnectionHandle::start(transport, connector, options)
+    }
+
+    /// Connect to a Unix domain socket forwarded to the API.
+    #[cfg(unix)]
+    pub async fn spawn_unix<P: AsRef<std::path::Path>>(path: P, options: ReconnectOptions) -> Result<ConnectionHandle, Error> {
+        let stream = tokio::net::UnixStream::connect(path.as_ref()).await?;
+        Ok(ConnectionHandle::start(stream, crate::transport::UnixConnector::new(path.as_ref()), options))
+    }
+
+    /// Replay a recorded session instead of connecting to the API, see [`Replay`].
+    pub fn replay(capture: Capture, options: ReplayOptions) -> Replay {
+        Replay::new(capture, options)
+    }
+
+    pub async fn start_tcp(&mut self, tcp_port: u32, tcp_address: String) -> Result<(), Error> {
         let addr: String = format!("{}:{}", tcp_address, tcp_port);
-        let stream = TcpStream::connect(addr).await.expect("failed to connect to tcp");
-        self.tcp_stream = Some(Arc::new(Mutex::new(stream)));
+        self.set_state(ConnectionState::Connecting);
+        let stream = match TcpStream::connect(addr).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                self.set_state(ConnectionState::Disconnected);
+                return Err(error.into())
+            },
+        };
+        self.start_transport(stream);
 
         Ok(())
     }
+}
 
-    pub async fn update(&mut self) -> Result<(), Box<dyn Error>> {
-        // Send get state for each state in the polling list if polling is enabled.
-        if self.enable_polling && self.last_poll.elapsed().as_millis() >= self.poll_interval as u128 {
-            for state in self.states_to_poll.clone() {
-                let result = self.get(state).await;
-                if let Err(error) = result {
-                    return Err(Box::new(error));
-                }
-            }
-            self.last_poll = Instant::now();
-        }
+impl<S: Transport> Connection<S> {
+    /// Create a connection over an already open transport, e.g. a TLS stream or one half of
+    /// an in-memory [`duplex`](crate::transport::duplex).
+    pub fn with_transport(transport: S) -> Self {
+        let mut connection = Self::default();
+        connection.start_transport(transport);
+        connection
+    }
 
-        let tcp_stream = self.tcp_stream.as_ref().unwrap().lock().await;
-        let ready = tcp_stream.ready(Interest::READABLE | Interest::WRITABLE).await?;
+    /// Use a newly opened transport, e.g. after the previous one was lost.
+    pub fn start_transport(&mut self, transport: S) {
+        self.transport = Some(transport);
+        self.set_state(ConnectionState::Connected);
+    }
 
-        if ready.is_readable() {
-            return match self.data.read(&tcp_stream).await {
-                Ok(_) => { Ok(()) },
-                Err(error) => {
-                    Err(Box::new(error))
-                }
-            };
+    /// Send the queued requests, then handle the data received from the API.
+    /// Returns after a short wait if nothing is received.
+    pub async fn update(&mut self) -> Result<(), Error> {
+        // Send get state for each subscribed state that is due to be polled.
+        for state_id in self.data.subscriptions().due_requests(Instant::now()) {
+            self.data.send_get_state(state_id).await;
         }
 
-        if ready.is_writable() {
-            return match self.data.send(&tcp_stream).await {
-                Ok(_) => { Ok(()) },
-                Err(error) => {
-                    Err(Box::new(error))
-                }
-            };
+        let result = {
+            let transport = self.transport.as_mut().ok_or(Error::Disconnected())?;
+            match self.data.send(transport).await {
+                Ok(()) => {
+                    let read_timeout = Duration::from_millis(UPDATE_READ_TIMEOUT);
+                    tokio::time::timeout(read_timeout, self.data.read(transport)).await.unwrap_or(Ok(()))
+                },
+                Err(error) => Err(error),
+            }
+        };
+        self.notify();
+
+        // the transport can't be used anymore after an I/O error, EOF or a corrupted frame
+        if let Err(Error::Io(_) | Error::Disconnected() | Error::Frame(_)) = &result {
+            self.transport = None;
+            self.data.clear_expected_responses();
+            self.set_state(ConnectionState::Disconnected);
         }
 
-        Ok(())
+        result
     }
 
     pub async fn get_manifest(&mut self) {
         self.data.send_get_state(-1).await
     }
 
-    pub async fn get(&mut self, state_path: String) -> Result<(), ManifestError> {
+    /// Resolve paths with the manifest cached for this instance right away, and revalidate it
+    /// by requesting the live manifest. The cache is updated whenever a received manifest differs.
+    ///
+    /// Returns whether a cached manifest was found.
+    #[cfg(feature = "discovery")]
+    pub async fn use_manifest_cache(&mut self, cache: ManifestCache, instance: &InstanceInformation) -> bool {
+        let found = self.data.set_manifest_cache(cache, ManifestCacheKey::from(instance));
+        self.notify();
+        self.get_manifest().await;
+        found
+    }
+
+    pub async fn get(&mut self, state_path: String) -> Result<(), Error> {
         let manifest = self.data.get_manifest()?;
         let entry = manifest.get_entry_by_path(&state_path)?;
         self.get_id(entry.id).await;
@@ -161,15 +237,13 @@ impl Connection {
         Ok(())
     }
 
-    pub async fn set(&self, state_path: String, value: TypedValue) -> Result<(), ManifestError> {
+    pub async fn set(&self, state_path: String, value: TypedValue) -> Result<(), Error> {
         let manifest = self.data.get_manifest()?;
         let entry = manifest.get_entry_by_path(&state_path)?;
-        self.set_id(entry.id, value).await;
-
-        Ok(())
+        self.set_id(entry.id, value).await
     }
 
-    pub async fn run(&self, command_path: String) -> Result<(), ManifestError> {
+    pub async fn run(&self, command_path: String) -> Result<(), Error> {
         let manifest = self.data.get_manifest()?;
         let entry = manifest.get_entry_by_path(&command_path)?;
         self.run_id(entry.id).await;
@@ -177,20 +251,170 @@ impl Connection {
         Ok(())
     }
 
+    /// Request the value of a state and get a future that resolves once the response arrives.
+    ///
+    /// The request is queued immediately; the returned future doesn't borrow the connection,
+    /// so it can (and, when the connection is shared behind a mutex, must) be awaited after
+    /// releasing the lock, while `update()` keeps running.
+    pub fn get_value(&mut self, state_path: &str, timeout: Duration) -> impl Future<Output = Result<TypedValue, Error>> + Send + 'static {
+        let state_id = self.data.get_manifest().and_then(|manifest| manifest.get_entry_by_path(state_path)).map(|entry| entry.id);
+        let request = state_id.map(|state_id| (state_id, self.data.request_value(state_id)));
+
+        async move {
+            let (state_id, receiver) = request?;
+            Self::await_response(state_id, receiver, timeout).await
+        }
+    }
+
+    /// Same as [`Connection::get_value`], but takes the state id directly.
+    pub fn get_value_id(&mut self, state_id: i32, timeout: Duration) -> impl Future<Output = Result<TypedValue, Error>> + Send + 'static {
+        let receiver = self.data.request_value(state_id);
+        Self::await_response(state_id, receiver, timeout)
+    }
+
+    async fn await_response(state_id: i32, receiver: oneshot::Receiver<Result<TypedValue, Error>>, timeout: Duration) -> Result<TypedValue, Error> {
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Error::Disconnected()),
+            Err(_) => Err(Error::Timeout(state_id)),
+        }
+    }
+
+    /// Fetch a snapshot of the user aircraft, requesting all of its states in one batch.
+    /// States the current aircraft doesn't have in its manifest are left empty.
+    ///
+    /// Like [`Connection::get_value`], the returned future doesn't borrow the connection.
+    pub fn poll_aircraft_state(&mut self, timeout: Duration) -> impl Future<Output = Result<AircraftState, Error>> + Send + 'static {
+        let state_ids = self.data.get_manifest().map(AircraftState::state_ids);
+        let request = state_ids.map(|state_ids| {
+            let (paths, state_ids): (Vec<&str>, Vec<i32>) = state_ids.into_iter().unzip();
+            (paths, self.get_values_id(&state_ids, timeout))
+        });
+
+        async move {
+            let (paths, values) = request?;
+            Ok(AircraftState::from_values(&paths, &values.await?))
+        }
+    }
+
+    /// Fetch every member of an indexed group like `aircraft/0/systems/engines/{}/n1`,
+    /// requesting them in one batch. The values are in index order.
+    pub fn get_indexed_values(&mut self, template: &str, timeout: Duration) -> impl Future<Output = Result<Vec<TypedValue>, Error>> + Send + 'static {
+        let state_ids = self.data.get_manifest().and_then(|manifest| manifest.indexed_group(template)).map(|group| group.state_ids());
+        let values = state_ids.map(|state_ids| self.get_values_id(&state_ids, timeout));
+
+        async move { values?.await }
+    }
+
+    /// Request the values of several states at once. The returned future resolves once all of
+    /// them are received; the timeout covers the whole batch.
+    pub fn get_values_id(&mut self, state_ids: &[i32], timeout: Duration) -> impl Future<Output = Result<Vec<TypedValue>, Error>> + Send + 'static {
+        let requests: Vec<_> = state_ids.iter().map(|state_id| (*state_id, self.data.request_value(*state_id))).collect();
+        await_values(requests, timeout)
+    }
+
+    /// The commands in the last received manifest.
+    pub fn commands(&self) -> Result<Commands, Error> {
+        Ok(Commands::from_manifest(self.data.get_manifest()?))
+    }
+
+    /// Resolve the states of an [`IfState`] struct in the current manifest and check their types.
+    pub fn bind_state<T: IfState>(&self) -> Result<StateBinding<T>, Error> {
+        StateBinding::bind(self.data.get_manifest()?)
+    }
+
+    /// Fetch an [`IfState`] struct, requesting all of its states in one batch.
+    ///
+    /// Like [`Connection::get_value`], the returned future doesn't borrow the connection.
+    pub fn fetch_state<T: IfState + Send + 'static>(&mut self, binding: &StateBinding<T>, timeout: Duration) -> impl Future<Output = Result<T, Error>> + Send + 'static {
+        let binding = binding.clone();
+        let values = self.get_values_id(&binding.bound_ids(), timeout);
+
+        async move { binding.build(values.await?) }
+    }
+
     pub async fn get_id(&mut self, state_id: i32) {
         self.data.send_get_state(state_id).await
     }
 
-    pub async fn set_id(&self, state_id: i32, value: TypedValue) {
-        self.data.send_set_state(state_id, value).await
+    /// Set a state. The value must match the type of the state in the manifest,
+    /// unless value coercion is enabled and it can be converted without loss.
+    pub async fn set_id(&self, state_id: i32, value: TypedValue) -> Result<(), Error> {
+        let value = self.data.check_set_value(state_id, value)?;
+        self.data.send_set_state(state_id, value).await;
+
+        Ok(())
+    }
+
+    /// Allow `set` to convert values to the type of the state (i32 to i64 or f64, f32 to f64, bool to i32).
+    /// Disabled by default.
+    pub fn set_value_coercion(&mut self, enabled: bool) {
+        self.data.set_coerce_values(enabled);
     }
 
     pub async fn run_id(&self, command_id: i32) {
         self.data.send_command(command_id).await
     }
 
-    pub fn get_connection_state(&self) -> &ConnectionState {
-        &self.state
+    /// Poll a state at the given interval while the returned subscription is alive.
+    ///
+    /// Subscribers of the same state share its requests: it is polled at the shortest of their intervals.
+    /// Polling happens in `update()`.
+    pub fn subscribe(&mut self, state_path: &str, interval: Duration) -> Result<Subscription, Error> {
+        let state_id = self.data.get_manifest()?.get_entry_by_path(state_path)?.id;
+        Ok(self.data.subscriptions().subscribe(state_id, Some(state_path), interval))
+    }
+
+    pub fn subscribe_id(&mut self, state_id: i32, interval: Duration) -> Subscription {
+        self.data.subscriptions().subscribe(state_id, None, interval)
+    }
+
+    /// Record the bytes going both ways to a capture, see [`Recorder`].
+    /// Replaces the recording in progress, if any.
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.data.set_recorder(Some(recorder));
+    }
+
+    /// Stop recording and flush the capture.
+    pub fn stop_recording(&mut self) -> Result<(), Error> {
+        match self.data.set_recorder(None) {
+            Some(mut recorder) => Ok(recorder.flush()?),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get_connection_state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Get a receiver that is notified whenever the connection state changes.
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    pub fn on_state_change<F: Fn(ConnectionStateArgs) + Send + 'static>(&mut self, func: Option<F>) {
+        match func {
+            Some(f) => {
+                self.data.set_state_changed_callback::<F>(Some(Box::new(f)));
+            },
+            None => {
+                self.data.set_state_changed_callback::<F>(None);
+            },
+        }
+    }
+
+    /// Call the data and manifest callbacks that became due.
+    fn notify(&mut self) {
+        for notification in self.data.take_notifications() {
+            notification.notify();
+        }
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        let previous_state = self.state.send_replace(state);
+        if let Some(change) = self.data.state_changed(previous_state, state) {
+            change.notify();
+        }
     }
 
     pub fn on_receive_data<F: Fn(ReceivedDataArgs) + Send + 'static>(&mut self, func: Option<F>) {
@@ -1,20 +1,62 @@
-use std::convert::{TryInto};
+use std::sync::Arc;
+use std::time::Duration;
 use queues::{IsQueue, Queue};
-use tokio::io;
-use tokio::io::{Error};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::capture::{Direction, Recorder};
+use crate::codec::{encode_get_request, encode_run_request, encode_set_request, FrameDecoder, MANIFEST_ID};
 use crate::manifest::Manifest;
-use crate::typed_value::{Type, TypedValue};
-use tokio::sync::{Mutex};
-use crate::error::ManifestError;
-use crate::event_args::{ReceivedDataArgs, ReceivedManifestArgs};
+use crate::manifest_cache::{ManifestCache, ManifestCacheKey};
+use crate::typed_value::TypedValue;
+use tokio::sync::{oneshot, Mutex};
+use crate::error::{Error, FrameError};
+use crate::connection::ConnectionState;
+use crate::event_args::{ConnectionStateArgs, ReceivedDataArgs, ReceivedManifestArgs};
+use crate::subscription::Subscriptions;
+
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// A get request waiting for its response.
+pub(crate) type PendingValue = (i32, oneshot::Receiver<Result<TypedValue, Error>>);
+
+/// Shared so it can be called once the lock on the connection data has been released.
+type Callback<A> = Arc<std::sync::Mutex<Box<dyn Fn(A) + Send>>>;
+
+/// A call to an event callback, made once the connection data is no longer locked, see
+/// [`ConnectionData::take_notifications`].
+#[must_use = "the callback is only called by `notify`"]
+pub struct Notification {
+    call: Box<dyn FnOnce() + Send>,
+}
+
+impl Notification {
+    fn new<A: Send + 'static>(callback: &Callback<A>, args: A) -> Self {
+        let callback = Arc::clone(callback);
+        Self {
+            call: Box::new(move || {
+                let callback = callback.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                callback(args)
+            }),
+        }
+    }
+
+    /// Call the callback. The callback may use the connection, so the connection data must not
+    /// be locked while calling this.
+    pub fn notify(self) {
+        (self.call)()
+    }
+}
 
+/// A response the API is expected to send, optionally with a caller waiting for its value.
+struct ExpectedResponse {
+    id: i32,
+    sender: Option<oneshot::Sender<Result<TypedValue, Error>>>,
+}
 
-pub enum ChunkType {
-    CommandId,
-    DataLength,
-    StringLength,
-    Data
+impl ExpectedResponse {
+    /// Whether the caller stopped waiting, e.g. because it timed out.
+    fn is_abandoned(&self) -> bool {
+        self.sender.as_ref().is_some_and(|sender| sender.is_closed())
+    }
 }
 
 /// Contains methods for dealing with the data received from / sent to IF
@@ -22,21 +64,30 @@ pub struct ConnectionData {
     manifest: Option<Manifest>,
 
     // helper fields for reading data from the API
-    expected_responses: Vec<i32>,
-    next_chunk_type: ChunkType,
-    current_id: i32,
-    current_data_length: i32,
-    current_string_len: i32,
-    current_data_string: String,
-
-    // queues for sending data to the API
-    id_queue: Mutex<Queue<i32>>,
-    bool_queue: Mutex<Queue<bool>>,
-    value_queue: Mutex<Queue<TypedValue>>,
+    expected_responses: Vec<ExpectedResponse>,
+    decoder: FrameDecoder,
+
+    // queue of encoded requests to send to the API
+    request_queue: Mutex<Queue<Vec<u8>>>,
+
+    // states polled on behalf of subscribers
+    subscriptions: Subscriptions,
+
+    // whether set values may be converted to the type of the state
+    coerce_values: bool,
+
+    // taps the bytes going both ways while recording
+    recorder: Option<Recorder>,
+
+    // where received manifests are saved for the next connection
+    manifest_cache: Option<(ManifestCache, ManifestCacheKey)>,
 
     // event callbacks
-    data_received_callback: Option<Box<dyn Fn(ReceivedDataArgs) + Send>>,
-    manifest_received_callback: Option<Box<dyn Fn(ReceivedManifestArgs) + Send>>,
+    data_received_callback: Option<Callback<ReceivedDataArgs>>,
+    manifest_received_callback: Option<Callback<ReceivedManifestArgs>>,
+    state_changed_callback: Option<Callback<ConnectionStateArgs>>,
+    // callbacks due since the last `take_notifications`
+    notifications: Vec<Notification>,
 }
 
 impl Default for ConnectionData {
@@ -45,18 +96,22 @@ impl Default for ConnectionData {
             manifest: None,
 
             expected_responses: Vec::new(),
-            next_chunk_type: ChunkType::CommandId,
-            current_id: 0,
-            current_data_length: 0,
-            current_string_len: 0,
-            current_data_string: String::new(),
+            decoder: FrameDecoder::new(),
+
+            request_queue: Mutex::new(Queue::new()),
+
+            subscriptions: Subscriptions::new(),
+
+            coerce_values: false,
 
-            id_queue: Mutex::new(Queue::new()),
-            bool_queue: Mutex::new(Queue::new()),
-            value_queue: Mutex::new(Queue::new()),
+            recorder: None,
+
+            manifest_cache: None,
 
             data_received_callback: None,
             manifest_received_callback: None,
+            state_changed_callback: None,
+            notifications: Vec::new(),
         }
     }
 }
@@ -66,334 +121,291 @@ impl ConnectionData {
         Self::default()
     }
 
-    pub async fn read(&mut self, tcp_stream: &TcpStream) -> Result<(), Error> {
-        let mut buf;
-        // determine buffer size depending on the next expected chunk
-        match self.next_chunk_type {
-            ChunkType::CommandId => {
-                buf = vec![0; 4];
-            },
-            ChunkType::DataLength => {
-                buf = vec![0; 4];
-            },
-            ChunkType::StringLength => {
-                buf = vec![0; 4];
-            },
-            ChunkType::Data => {
-                buf = vec![0; 1024];
-            },
-        }
+    /// Wait for bytes from the stream and handle them. Cancelling the returned future loses no data.
+    pub async fn read<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> Result<(), Error> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
 
-        return match tcp_stream.try_read(&mut buf) {
-            Ok(len) => {
-                // abort if there are no bytes
-                if len == 0 { return Ok(()) }
+        let len = reader.read(&mut buf).await?;
+        // no bytes means the API closed the connection
+        if len == 0 { return Err(Error::Disconnected()) }
 
-                self.read_chunk(buf, len);
-
-                Ok(())
-            },
-            // may still fail with `WouldBlock` if the readiness event is a false positive.
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                Err(e)
-            },
-            Err(e) => {
-                Err(e)
-            },
-        }
+        self.receive_bytes(&buf[0..len])
     }
 
-    /// Reads the received data chunk
-    pub fn read_chunk(&mut self, bytes: Vec<u8>, length: usize) {
-        match self.next_chunk_type {
-            ChunkType::CommandId => {
-                let id = Self::read_le_i32(&mut bytes[0..(length)].as_ref());
-                self.id_received(id);
-            },
-            ChunkType::DataLength => {
-                let len = Self::read_le_i32(&mut bytes[0..(length)].as_ref());
-                self.data_length_received(len);
-            },
-            ChunkType::StringLength => {
-                let str_len = Self::read_le_i32(&mut bytes[0..(length)].as_ref());
-                self.string_length_received(str_len);
-            },
-            ChunkType::Data => {
-                self.data_chunk_received(&bytes, &length);
-            },
+    /// Feed received bytes into the decoder and handle every frame they complete.
+    pub fn receive_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.record(Direction::Received, bytes);
+        self.decoder.extend(bytes);
+
+        loop {
+            match self.decoder.next_value(self.manifest.as_ref()) {
+                Ok(Some((id, value))) => self.value_received(id, value),
+                Ok(None) => return Ok(()),
+                // the frame has been consumed, so the stream is still in sync
+                Err(Error::Frame(FrameError::UnknownType(id))) => {
+                    log::warn!("Received a response with an unknown data type for id {}, skipping it.", id);
+                },
+                Err(error) => return Err(error),
+            }
         }
     }
 
     pub async fn send_get_state(&mut self, state_id: i32) {
-        // add this id to the expected responses array
-        self.expected_responses.push(state_id);
+        self.expect_response(state_id);
 
-        let mut ids_lock = self.id_queue.lock().await;
-        let mut bool_lock = self.bool_queue.lock().await;
-
-        ids_lock.add(state_id).unwrap();
-        bool_lock.add(false).unwrap();
-    }
-
-    pub async fn send_set_state(&self, state_id: i32, value: TypedValue) {
-        let mut ids_lock = self.id_queue.lock().await;
-        let mut bool_lock = self.bool_queue.lock().await;
-        let mut values_lock = self.value_queue.lock().await;
-
-        ids_lock.add(state_id).unwrap();
-        bool_lock.add(true).unwrap();
-        values_lock.add(value).unwrap();
+        Self::queue_request(&self.request_queue, encode_get_request(state_id)).await;
     }
 
-    pub async fn send_command(&self, command_id: i32) {
-        let mut ids_lock = self.id_queue.lock().await;
-        let mut bool_lock = self.bool_queue.lock().await;
+    /// Queue a get state request and return a receiver resolved with the response value.
+    /// Responses for the same id are matched to requests in the order they were sent.
+    pub fn request_value(&mut self, state_id: i32) -> oneshot::Receiver<Result<TypedValue, Error>> {
+        let receiver = self.expect_value(state_id);
 
-        ids_lock.add(command_id).unwrap();
-        bool_lock.add(false).unwrap();
-    }
+        // &mut self guarantees the queue isn't locked elsewhere
+        self.request_queue.get_mut().add(encode_get_request(state_id)).unwrap();
 
-    pub fn set_received_data_callback<F: Fn(ReceivedDataArgs) + Send + 'static>(&mut self, func: Option<Box<dyn Fn(ReceivedDataArgs) + Send + 'static>>)
-    {
-        self.data_received_callback = func;
+        receiver
     }
 
-    pub fn set_received_manifest_callback<F: Fn(ReceivedManifestArgs) + Send + 'static>(&mut self, func: Option<Box<dyn Fn(ReceivedManifestArgs) + Send + 'static>>)
-    {
-        self.manifest_received_callback = func;
+    /// Add this id to the expected responses array, for requests sent outside of the queue.
+    pub fn expect_response(&mut self, state_id: i32) {
+        self.expected_responses.push(ExpectedResponse { id: state_id, sender: None });
     }
 
-    fn id_received(&mut self, id: i32) {
-        // if present, remove this id from the expected responses array.
-        // if this id is not expected, print a warning.
-        if !self.expected_responses.contains(&id) {
-            println!("Received an unexpected response from API, reading it anyway.");
-        } else {
-            for (index, expected_response_id) in self.expected_responses.iter().enumerate() {
-                if id == *expected_response_id {
-                    self.expected_responses.swap_remove(index);
-                    break;
-                }
-            }
-        }
+    /// Same as [`ConnectionData::expect_response`], returning a receiver resolved with the response value.
+    pub fn expect_value(&mut self, state_id: i32) -> oneshot::Receiver<Result<TypedValue, Error>> {
+        let (sender, receiver) = oneshot::channel();
+        // requests the API never answered would otherwise pile up
+        self.expected_responses.retain(|expected| !expected.is_abandoned());
+        self.expected_responses.push(ExpectedResponse { id: state_id, sender: Some(sender) });
 
-        self.current_id = id;
-        self.next_chunk_type = ChunkType::DataLength;
+        receiver
     }
 
-    fn data_length_received(&mut self, data_length: i32) {
-        self.current_data_length = data_length;
+    /// Forget every expected response, failing the callers waiting for their values.
+    pub fn clear_expected_responses(&mut self) {
+        self.expected_responses.clear();
+        self.decoder.clear();
+    }
 
-        let manifest = &self.manifest;
+    /// Check a value against the manifest before setting it,
+    /// converting it to the type of the state if value coercion is enabled.
+    pub fn check_set_value(&self, state_id: i32, value: TypedValue) -> Result<TypedValue, Error> {
+        self.get_manifest()?.check_set_value(state_id, value, self.coerce_values)
+    }
 
-        // determine the next expected chunk based on the command id
-        if self.current_id == -1 {
-            self.next_chunk_type = ChunkType::StringLength;
-        } else {
-            let curr_datatype = manifest.as_ref().unwrap().get_data_type_for_id(&self.current_id);
-            if curr_datatype.is_ok() {
-                if curr_datatype.as_ref().unwrap() == &Type::String {
-                    self.next_chunk_type = ChunkType::StringLength;
-                } else {
-                    self.next_chunk_type = ChunkType::Data;
-                }
-            }
-        }
+    /// Record the bytes going both ways from now on, replacing the current recorder.
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) -> Option<Recorder> {
+        std::mem::replace(&mut self.recorder, recorder)
     }
 
-    fn string_length_received(&mut self, string_length: i32) {
-        self.current_string_len = string_length;
-        self.next_chunk_type = ChunkType::Data;
+    /// Record a request written to the API outside of the queue.
+    pub fn request_sent(&mut self, request: &[u8]) {
+        self.record(Direction::Sent, request);
     }
 
-    fn data_chunk_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        if self.manifest.is_none() || self.current_id == -1 {
-            self.manifest_chunk_received(bytes, bytes_length);
-        } else {
-            let manifest = self.manifest.as_ref().unwrap();
+    /// Expect the response to a request sent in a recorded session, so it is matched
+    /// to it like it originally was. Commands and set requests don't get a response.
+    pub fn request_replayed(&mut self, request: &[u8]) {
+        if request.len() < 8 || request[4..8] != [0; 4] {
+            return
+        }
 
-            match manifest.get_data_type_for_id(&self.current_id).unwrap() {
-                Type::String => self.string_chunk_received(bytes, bytes_length),
-                Type::Long => self.long_received(bytes, bytes_length),
-                Type::Boolean => self.boolean_received(bytes, bytes_length),
-                Type::Integer32 => self.i32_received(bytes, bytes_length),
-                Type::Float => self.float_received(bytes, bytes_length),
-                Type::Double => self.double_received(bytes, bytes_length),
-            }
+        let id = i32::from_le_bytes(request[0..4].try_into().unwrap());
+        let is_command = self.manifest.as_ref()
+            .and_then(|manifest| manifest.get_entry_by_id(&id).ok())
+            .is_some_and(|entry| entry.is_command());
+        if !is_command {
+            self.expect_response(id);
         }
     }
 
-    fn manifest_chunk_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        let str_chunk = String::from_utf8_lossy(&bytes[0..*(bytes_length)]);
-        self.current_data_string = self.current_data_string.to_owned() + str_chunk.as_ref();
-
-        if self.current_data_string.as_bytes().len() >= self.current_string_len as usize {
-            //println!("done reading manifest: {} bytes total", self.current_data_string.as_bytes().len());
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        let recorder = match &mut self.recorder {
+            Some(recorder) => recorder,
+            None => return,
+        };
 
-            // parse the manifest
-            let manifest = Manifest::from_str(&self.current_data_string);
-            self.manifest = Some(manifest.clone());
-
-            if let Some(callback) = &self.manifest_received_callback {
-                callback(ReceivedManifestArgs::new(manifest.clone()))
-            }
+        // a broken capture shouldn't take the connection down with it
+        if let Err(error) = recorder.record(direction, bytes) {
+            log::warn!("Failed to write to the capture, stopping the recording: {}", error);
+            self.recorder = None;
         }
     }
 
-    fn string_chunk_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        let str_chunk = String::from_utf8_lossy(&bytes[0..*(bytes_length)]);
-        self.current_data_string = self.current_data_string.to_owned() + str_chunk.as_ref();
-
-        if self.current_data_string.as_bytes().len() >= self.current_string_len as usize {
-            //println!("done reading string: {} bytes total", self.current_data_string.as_bytes().len());
-
-            if let Some(callback) = &self.data_received_callback {
-                callback(ReceivedDataArgs::new(self.current_id, TypedValue::String(self.current_data_string.clone())))
-            }
+    pub fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
 
-            //println!("full string: \n{}", self.current_data_string);
-        }
+    pub fn set_coerce_values(&mut self, coerce_values: bool) {
+        self.coerce_values = coerce_values;
     }
 
-    fn double_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        let data = Self::read_le_f64(&mut bytes[0..*(bytes_length)].as_ref());
+    pub async fn send_set_state(&self, state_id: i32, value: TypedValue) {
+        Self::queue_request(&self.request_queue, encode_set_request(state_id, &value)).await;
+    }
 
-        if let Some(callback) = &self.data_received_callback {
-            callback(ReceivedDataArgs::new(self.current_id, TypedValue::Double(data)))
-        }
+    pub async fn send_command(&self, command_id: i32) {
+        Self::queue_request(&self.request_queue, encode_run_request(command_id)).await;
     }
 
-    fn float_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        let data = Self::read_le_f32(&mut bytes[0..*(bytes_length)].as_ref());
+    async fn queue_request(request_queue: &Mutex<Queue<Vec<u8>>>, request: Vec<u8>) {
+        let mut queue_lock = request_queue.lock().await;
+        queue_lock.add(request).unwrap();
+    }
 
-        if let Some(callback) = &self.data_received_callback {
-            callback(ReceivedDataArgs::new(self.current_id, TypedValue::Float(data)))
-        }
+    pub fn set_received_data_callback<F: Fn(ReceivedDataArgs) + Send + 'static>(&mut self, func: Option<Box<dyn Fn(ReceivedDataArgs) + Send + 'static>>)
+    {
+        self.data_received_callback = func.map(|func| Arc::new(std::sync::Mutex::new(func)));
     }
 
-    fn i32_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        let data = Self::read_le_i32(&mut bytes[0..*(bytes_length)].as_ref());
+    pub fn set_received_manifest_callback<F: Fn(ReceivedManifestArgs) + Send + 'static>(&mut self, func: Option<Box<dyn Fn(ReceivedManifestArgs) + Send + 'static>>)
+    {
+        self.manifest_received_callback = func.map(|func| Arc::new(std::sync::Mutex::new(func)));
+    }
 
-        if let Some(callback) = &self.data_received_callback {
-            callback(ReceivedDataArgs::new(self.current_id, TypedValue::Integer32(data)))
-        }
+    pub fn set_state_changed_callback<F: Fn(ConnectionStateArgs) + Send + 'static>(&mut self, func: Option<Box<dyn Fn(ConnectionStateArgs) + Send + 'static>>)
+    {
+        self.state_changed_callback = func.map(|func| Arc::new(std::sync::Mutex::new(func)));
     }
 
-    fn boolean_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        let data = Self::read_bool(&mut bytes[0..*(bytes_length)].as_ref());
+    /// The call to the state change callback, if there is one and the state actually changed.
+    pub fn state_changed(&self, previous_state: ConnectionState, state: ConnectionState) -> Option<Notification> {
+        if previous_state == state { return None }
 
-        if let Some(callback) = &self.data_received_callback {
-            callback(ReceivedDataArgs::new(self.current_id, TypedValue::Boolean(data)))
-        }
+        self.state_changed_callback.as_ref()
+            .map(|callback| Notification::new(callback, ConnectionStateArgs::new(previous_state, state)))
     }
 
-    fn long_received(&mut self, bytes: &Vec<u8>, bytes_length: &usize) {
-        let data = Self::read_le_i64(&mut bytes[0..*(bytes_length)].as_ref());
-
-        if let Some(callback) = &self.data_received_callback {
-            callback(ReceivedDataArgs::new(self.current_id, TypedValue::Long(data)))
-        }
+    /// The data and manifest callbacks due since the last call, in the order their events happened.
+    /// Call them once the connection data is no longer locked.
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
     }
 
-    pub async fn send(&mut self, tcp_stream: &TcpStream) -> Result<(), Error> {
-        // ensure the queues can be locked, otherwise skip
-        let ids_lock = self.id_queue.try_lock();
-        let bool_lock = self.bool_queue.try_lock();
-        let values_lock = self.value_queue.try_lock();
-        if ids_lock.is_err() || bool_lock.is_err() || values_lock.is_err() { return Ok(()) }
+    fn value_received(&mut self, id: i32, value: TypedValue) {
+        // the manifest is parsed before anyone waiting for it is notified
+        let result = match (id, &value) {
+            (MANIFEST_ID, TypedValue::String(manifest_str)) => self.manifest_received(manifest_str).map(|_| value),
+            _ => Ok(value),
+        };
 
-        // if there is nothing to write, skip
-        if ids_lock.as_ref().unwrap().size() == 0 && bool_lock.as_ref().unwrap().size() == 0 && values_lock.as_ref().unwrap().size() == 0 {
-            return Ok(())
-        }
+        // if present, remove this id from the expected responses array.
+        // if this id is not expected, log it.
+        // callers that timed out don't get a response, the next one waiting for this id does
+        self.expected_responses.retain(|expected| expected.id != id || !expected.is_abandoned());
+        let sender = match self.expected_responses.iter().position(|expected| expected.id == id) {
+            // the oldest request for this id gets this response, keep the order for the rest
+            Some(index) => self.expected_responses.remove(index).sender,
+            None => {
+                log::debug!("Received an unexpected response from API for id {}, reading it anyway.", id);
+                None
+            },
+        };
 
-        // write id
-        let mut id_queue = ids_lock.unwrap();
-        let next_id_entry = id_queue.remove();
-        if next_id_entry.is_ok() {
-            match tcp_stream.try_write(&next_id_entry.unwrap().to_le_bytes()) {
-                Ok(_n) => {
-                    //println!("write {} id bytes", n);
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    //return Ok(())
-                }
-                Err(_e) => {
-                    //return Err(e.into())
-                }
+        if id != MANIFEST_ID {
+            if let Ok(value) = &result {
+                self.subscriptions.deliver(id, value);
+            }
+            if let (Ok(value), Some(callback)) = (&result, &self.data_received_callback) {
+                self.notifications.push(Notification::new(callback, ReceivedDataArgs::new(id, value.clone())));
             }
         }
 
-        // write bool
-        let mut bool_queue = bool_lock.unwrap();
-        let next_bool_entry = bool_queue.remove();
-        if next_bool_entry.is_ok() {
-            match tcp_stream.try_write(&(next_bool_entry.unwrap() as i32).to_le_bytes()) {
-                Ok(_n) => {
-                    //println!("write {} bool bytes", n);
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    //return Ok(())
-                }
-                Err(_e) => {
-                    //return Err(e.into())
-                }
-            }
+        match (sender, result) {
+            // the caller may have timed out already
+            (Some(sender), result) => { let _ = sender.send(result); },
+            (None, Err(error)) => log::error!("Failed to handle the response for id {}: {}", id, error),
+            (None, Ok(_)) => {},
         }
+    }
 
-        // write value
-        let mut values_queue = values_lock.unwrap();
-        let next_values_entry = values_queue.remove();
-        if next_values_entry.is_ok() {
-            match tcp_stream.try_write(&(next_values_entry.unwrap()).to_bytes_vec()) {
-                Ok(_n) => {
-                    //println!("write {} value bytes", n);
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    //return Ok(())
-                }
-                Err(_e) => {
-                    //return Err(e.into())
+    /// Use the cached manifest for this key until the live one is received, and save the live one
+    /// to the cache if it differs. Returns whether a cached manifest was found.
+    pub fn set_manifest_cache(&mut self, cache: ManifestCache, key: ManifestCacheKey) -> bool {
+        let cached = cache.load(&key);
+        self.manifest_cache = Some((cache, key));
+
+        match cached {
+            Some(manifest) if self.manifest.is_none() => {
+                self.set_manifest(manifest);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn manifest_received(&mut self, manifest_str: &str) -> Result<(), Error> {
+        // parse the manifest
+        let manifest = Manifest::parse(manifest_str)?;
+
+        // the cached manifest is stale if it differs from the live one
+        if let Some((cache, key)) = &self.manifest_cache {
+            if self.manifest.as_ref() != Some(&manifest) {
+                if let Err(error) = cache.store(key, &manifest) {
+                    log::warn!("Failed to update the manifest cache: {}", error);
                 }
             }
         }
 
+        self.set_manifest(manifest);
         Ok(())
     }
 
-    pub fn get_manifest(&self) -> Result<&Manifest, ManifestError> {
-        if self.manifest.is_some() {
-            Ok(self.manifest.as_ref().unwrap())
-        } else {
-            Err(ManifestError::NoManifest())
+    fn set_manifest(&mut self, manifest: Manifest) {
+        self.subscriptions.rebind(&manifest);
+        self.manifest = Some(manifest.clone());
+
+        if let Some(callback) = &self.manifest_received_callback {
+            self.notifications.push(Notification::new(callback, ReceivedManifestArgs::new(manifest)));
         }
     }
 
-    fn read_le_i32(input: &mut &[u8]) -> i32 {
-        let (bytes, rest) = input.split_at(std::mem::size_of::<i32>());
-        *input = rest;
-        i32::from_le_bytes(bytes.try_into().unwrap())
-    }
+    /// Write every queued request to the stream.
+    pub async fn send<W: AsyncWrite + Unpin>(&mut self, writer: &mut W) -> Result<(), Error> {
+        loop {
+            // ensure the queue can be locked, otherwise skip
+            let request = match self.request_queue.try_lock() {
+                Ok(mut queue_lock) => queue_lock.remove().ok(),
+                Err(_) => return Ok(()),
+            };
+
+            // if there is nothing to write, skip
+            let request = match request {
+                Some(request) => request,
+                None => break,
+            };
+            self.record(Direction::Sent, &request);
+            writer.write_all(&request).await?;
+        }
 
-    fn read_le_f32(input: &mut &[u8]) -> f32 {
-        let (bytes, rest) = input.split_at(std::mem::size_of::<f32>());
-        *input = rest;
-        f32::from_le_bytes(bytes.try_into().unwrap())
+        writer.flush().await?;
+        Ok(())
     }
 
-    fn read_le_f64(input: &mut &[u8]) -> f64 {
-        let (bytes, rest) = input.split_at(std::mem::size_of::<f64>());
-        *input = rest;
-        f64::from_le_bytes(bytes.try_into().unwrap())
+    pub fn get_manifest(&self) -> Result<&Manifest, Error> {
+        self.manifest.as_ref().ok_or(Error::NoManifest())
     }
+}
 
-    fn read_le_i64(input: &mut &[u8]) -> i64 {
-        let (bytes, rest) = input.split_at(std::mem::size_of::<i64>());
-        *input = rest;
-        i64::from_le_bytes(bytes.try_into().unwrap())
+/// Wait for the responses to a batch of get requests, in the order they were requested in.
+/// The timeout covers the whole batch.
+pub(crate) async fn await_values(requests: Vec<PendingValue>, timeout: Duration) -> Result<Vec<TypedValue>, Error> {
+    // timeouts too long to represent never expire
+    let deadline = tokio::time::Instant::now().checked_add(timeout);
+    let mut values = Vec::with_capacity(requests.len());
+    for (state_id, receiver) in requests {
+        let response = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, receiver).await,
+            None => Ok(receiver.await),
+        };
+        let value = match response {
+            Ok(Ok(result)) => result?,
+            Ok(Err(_)) => return Err(Error::Disconnected()),
+            Err(_) => return Err(Error::Timeout(state_id)),
+        };
+        values.push(value);
     }
 
-    fn read_bool(input: &mut &[u8]) -> bool {
-        input.ends_with(&[1u8])
-    }
+    Ok(values)
 }
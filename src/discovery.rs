@@ -0,0 +1,195 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use crate::error::{DiscoveryError, Error};
+use crate::UDP_PORT;
+
+const UDP_DISCOVERY_ADDRESS: &str = "0.0.0.0";
+const RECEIVE_BUFFER_SIZE: usize = 4096;
+const DEFAULT_LOST_AFTER: u64 = 5; // s
+const DEFAULT_DISCOVERY_WINDOW: u64 = 5; // s
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstanceInformation {
+    pub state: String,
+    pub port: u32,
+    #[serde(rename = "DeviceID")]
+    pub device_id: String,
+    pub aircraft: String,
+    pub version: String,
+    pub device_name: String,
+    pub addresses: Vec<String>,
+    pub livery: String
+}
+
+#[derive(Debug, Clone)]
+pub enum DiscoveryEvent {
+    /// An instance broadcast for the first time (or again after being lost).
+    Found(InstanceInformation),
+    /// An instance stopped broadcasting for longer than `lost_after`.
+    Lost(InstanceInformation),
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    pub port: u16,
+    /// How long to listen for. `None` listens until the stream is dropped.
+    pub window: Option<Duration>,
+    /// How long an instance may stay silent before it is reported as lost.
+    pub lost_after: Duration,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        Self {
+            port: UDP_PORT as u16,
+            window: Some(Duration::from_secs(DEFAULT_DISCOVERY_WINDOW)),
+            lost_after: Duration::from_secs(DEFAULT_LOST_AFTER),
+        }
+    }
+}
+
+struct DiscoveryState {
+    socket: UdpSocket,
+    deadline: Option<Instant>,
+    lost_after: Duration,
+    instances: HashMap<String, (InstanceInformation, Instant)>,
+    events: VecDeque<DiscoveryEvent>,
+    done: bool,
+}
+
+/// Listen for IF instances broadcasting on the LAN.
+///
+/// Instances are de-duplicated by their device id; the stream yields an event whenever one
+/// appears or disappears and ends once the window is over. Messages that fail to parse are
+/// reported as errors without ending the stream.
+pub async fn discover(options: DiscoveryOptions) -> Result<impl Stream<Item = Result<DiscoveryEvent, Error>>, Error> {
+    let socket = UdpSocket::bind((UDP_DISCOVERY_ADDRESS, options.port)).await.map_err(DiscoveryError::Bind)?;
+
+    let state = DiscoveryState {
+        socket,
+        deadline: options.window.map(|window| Instant::now() + window),
+        lost_after: options.lost_after,
+        instances: HashMap::new(),
+        events: VecDeque::new(),
+        done: false,
+    };
+
+    Ok(futures::stream::unfold(state, |mut state| async move {
+        let event = state.next_event().await?;
+        Some((event, state))
+    }))
+}
+
+/// Listen for the whole discovery window and return every instance that was still broadcasting at its end.
+/// Uses the default window if the options don't set one.
+pub async fn discover_instances(mut options: DiscoveryOptions) -> Result<Vec<InstanceInformation>, Error> {
+    if options.window.is_none() {
+        options.window = DiscoveryOptions::default().window;
+    }
+
+    let mut instances = HashMap::new();
+    let stream = discover(options).await?;
+    futures::pin_mut!(stream);
+    while let Some(event) = futures::StreamExt::next(&mut stream).await {
+        match event {
+            Ok(DiscoveryEvent::Found(instance)) => { instances.insert(instance.device_id.clone(), instance); },
+            Ok(DiscoveryEvent::Lost(instance)) => { instances.remove(&instance.device_id); },
+            // a malformed broadcast doesn't invalidate the others
+            Err(Error::Discovery(DiscoveryError::InvalidMessage(_))) => {},
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(instances.into_values().collect())
+}
+
+/// Parse a discovery broadcast, ignoring the trailing null characters.
+pub fn parse_instance_information(bytes: &[u8]) -> Result<InstanceInformation, Error> {
+    let message = std::str::from_utf8(bytes).map_err(|error| DiscoveryError::InvalidMessage(error.to_string()))?;
+    let instance = serde_json::from_str(message.trim_matches(char::from(0))).map_err(|error| DiscoveryError::InvalidMessage(error.to_string()))?;
+    Ok(instance)
+}
+
+impl DiscoveryState {
+    async fn next_event(&mut self) -> Option<Result<DiscoveryEvent, Error>> {
+        let mut buf = [0u8; RECEIVE_BUFFER_SIZE];
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event))
+            }
+            if self.done {
+                return None
+            }
+
+            let now = Instant::now();
+            if self.deadline.is_some_and(|deadline| now >= deadline) {
+                self.done = true;
+                continue
+            }
+
+            self.expire_instances(now);
+            if !self.events.is_empty() {
+                continue
+            }
+
+            // wake up for whichever comes first: the end of the window or the next instance to expire
+            let next_expiry = self.instances.values().map(|(_, last_seen)| *last_seen + self.lost_after).min();
+            let wake_at = match (self.deadline, next_expiry) {
+                (Some(deadline), Some(expiry)) => Some(deadline.min(expiry)),
+                (deadline, expiry) => deadline.or(expiry),
+            };
+
+            let received = match wake_at {
+                Some(wake_at) => match tokio::time::timeout_at(wake_at, self.socket.recv(&mut buf)).await {
+                    Ok(result) => result,
+                    Err(_) => continue,
+                },
+                None => self.socket.recv(&mut buf).await,
+            };
+
+            match received {
+                Ok(len) => {
+                    let instance = match parse_instance_information(&buf[0..len]) {
+                        Ok(instance) => instance,
+                        Err(error) => return Some(Err(error)),
+                    };
+                    self.instance_seen(instance);
+                },
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(DiscoveryError::Receive(error).into()))
+                },
+            }
+        }
+    }
+
+    fn instance_seen(&mut self, instance: InstanceInformation) {
+        let now = Instant::now();
+        match self.instances.get_mut(&instance.device_id) {
+            Some(known) => *known = (instance, now),
+            None => {
+                self.instances.insert(instance.device_id.clone(), (instance.clone(), now));
+                self.events.push_back(DiscoveryEvent::Found(instance));
+            },
+        }
+    }
+
+    fn expire_instances(&mut self, now: Instant) {
+        let lost_after = self.lost_after;
+        let lost: Vec<String> = self.instances.iter()
+            .filter(|(_, (_, last_seen))| now.duration_since(*last_seen) >= lost_after)
+            .map(|(device_id, _)| device_id.clone())
+            .collect();
+
+        for device_id in lost {
+            if let Some((instance, _)) = self.instances.remove(&device_id) {
+                self.events.push_back(DiscoveryEvent::Lost(instance));
+            }
+        }
+    }
+}
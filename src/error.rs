@@ -1,23 +1,230 @@
 use core::fmt;
-use std::error::Error;
+use std::error::Error as StdError;
+use crate::typed_value::Type;
 
-#[derive(Debug, Clone)]
-pub enum ManifestError{
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Every error returned by the crate.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Discovery(DiscoveryError),
+    Frame(FrameError),
+    ManifestParse(ManifestParseError),
+    Capture(CaptureError),
+    Value(ValueError),
     NoSuchEntryId(i32),
     NoSuchEntryPath(String),
     WrongDataType(i32),
     NoManifest(),
+    TypeMismatch(i32, Type, Type),
+    NotSettable(i32),
+    Timeout(i32),
+    MissingValue(i32),
+    InvalidState(&'static str),
+    ConstantCollision(String, String, String),
+    Disconnected(),
 }
 
-impl Error for ManifestError {}
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Discovery(error) => Some(error),
+            Error::Frame(error) => Some(error),
+            Error::ManifestParse(error) => Some(error),
+            Error::Capture(error) => Some(error),
+            Error::Value(error) => Some(error),
+            _ => None,
+        }
+    }
+}
 
-impl fmt::Display for ManifestError {
+impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ManifestError::NoSuchEntryId(id) => write!(f, "Manifest error: no entry with id: {}", id),
-            ManifestError::NoSuchEntryPath(path) => write!(f, "Manifest error: no entry with path: {}", path),
-            ManifestError::WrongDataType(data_type) => write!(f, "Manifest error: entry has unknown data type: {}", data_type),
-            ManifestError::NoManifest() => write!(f, "Manifest error: manifest has not been retrieved yet"),
+            Error::Io(error) => write!(f, "I/O error: {}", error),
+            Error::Discovery(error) => write!(f, "{}", error),
+            Error::Frame(error) => write!(f, "{}", error),
+            Error::ManifestParse(error) => write!(f, "{}", error),
+            Error::Capture(error) => write!(f, "{}", error),
+            Error::Value(error) => write!(f, "{}", error),
+            Error::NoSuchEntryId(id) => write!(f, "Manifest error: no entry with id: {}", id),
+            Error::NoSuchEntryPath(path) => write!(f, "Manifest error: no entry with path: {}", path),
+            Error::WrongDataType(data_type) => write!(f, "Manifest error: entry has unknown data type: {}", data_type),
+            Error::NoManifest() => write!(f, "Manifest error: manifest has not been retrieved yet"),
+            Error::TypeMismatch(id, expected, actual) => write!(f, "Type error: entry {} expects a value of type {:?}, got {:?}", id, expected, actual),
+            Error::NotSettable(id) => write!(f, "Type error: entry {} is a command and can't be set", id),
+            Error::Timeout(id) => write!(f, "Request error: timed out waiting for a response for id: {}", id),
+            Error::MissingValue(id) => write!(f, "Request error: no value received for id: {}", id),
+            Error::InvalidState(name) => write!(f, "Type error: the values received don't fit the fields of {}", name),
+            Error::ConstantCollision(name, first_path, second_path) => write!(f, "Generation error: {} and {} would both be named {}", first_path, second_path, name),
+            Error::Disconnected() => write!(f, "Connection error: not connected to the API"),
         }
     }
 }
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(error: FrameError) -> Self {
+        Error::Frame(error)
+    }
+}
+
+impl From<ManifestParseError> for Error {
+    fn from(error: ManifestParseError) -> Self {
+        Error::ManifestParse(error)
+    }
+}
+
+impl From<CaptureError> for Error {
+    fn from(error: CaptureError) -> Self {
+        Error::Capture(error)
+    }
+}
+
+impl From<ValueError> for Error {
+    fn from(error: ValueError) -> Self {
+        Error::Value(error)
+    }
+}
+
+impl From<DiscoveryError> for Error {
+    fn from(error: DiscoveryError) -> Self {
+        Error::Discovery(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FrameError {
+    NegativeLength(i32, i32),
+    TooLarge(i32, usize),
+    PayloadLength(i32, usize, usize),
+    StringLength(i32, i32, usize),
+    InvalidUtf8(i32),
+    UnknownType(i32),
+}
+
+impl StdError for FrameError {}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::NegativeLength(id, length) => write!(f, "Frame error: negative length {} for id: {}", length, id),
+            FrameError::TooLarge(id, length) => write!(f, "Frame error: length {} for id {} exceeds the maximum frame length", length, id),
+            FrameError::PayloadLength(id, expected, actual) => write!(f, "Frame error: expected {} payload bytes for id {}, got {}", expected, id, actual),
+            FrameError::StringLength(id, string_length, available) => write!(f, "Frame error: string length {} for id {} does not match the {} bytes available", string_length, id, available),
+            FrameError::InvalidUtf8(id) => write!(f, "Frame error: string for id {} is not valid UTF-8", id),
+            FrameError::UnknownType(id) => write!(f, "Frame error: data type for id {} is unknown", id),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    Bind(std::io::Error),
+    Receive(std::io::Error),
+    InvalidMessage(String),
+}
+
+impl StdError for DiscoveryError {}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscoveryError::Bind(error) => write!(f, "Discovery error: failed to bind the udp socket: {}", error),
+            DiscoveryError::Receive(error) => write!(f, "Discovery error: failed to receive from the udp socket: {}", error),
+            DiscoveryError::InvalidMessage(error) => write!(f, "Discovery error: failed to parse the instance information: {}", error),
+        }
+    }
+}
+
+/// Error from reading a capture file, with the index of the offending record where there is one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    InvalidDirection(usize, u8),
+    Truncated(usize),
+}
+
+impl StdError for CaptureError {}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::InvalidHeader => write!(f, "Capture error: not a capture file"),
+            CaptureError::UnsupportedVersion(version) => write!(f, "Capture error: unsupported capture version: {}", version),
+            CaptureError::InvalidDirection(index, direction) => write!(f, "Capture error: invalid direction {} in record {}", direction, index),
+            CaptureError::Truncated(index) => write!(f, "Capture error: record {} is truncated", index),
+        }
+    }
+}
+
+/// Error from converting a value to another type or parsing it from a string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    /// The value can't be converted to the expected type without loss: (expected, actual).
+    WrongType(Type, Type),
+    /// The input isn't a valid value of the type.
+    Invalid(Type, String),
+}
+
+impl StdError for ValueError {}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueError::WrongType(expected, actual) => write!(f, "Value error: expected a value of type {:?}, got {:?}", expected, actual),
+            ValueError::Invalid(data_type, input) => write!(f, "Value error: {:?} is not a valid value of type {:?}", input, data_type),
+        }
+    }
+}
+
+/// Error from parsing a manifest, with the line number (starting at 1) and content of the offending line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestParseError {
+    MissingField(usize, String),
+    InvalidId(usize, String),
+    InvalidDataType(usize, String),
+    UnknownDataType(usize, String),
+}
+
+impl ManifestParseError {
+    pub fn line_number(&self) -> usize {
+        match self {
+            ManifestParseError::MissingField(line_number, _)
+            | ManifestParseError::InvalidId(line_number, _)
+            | ManifestParseError::InvalidDataType(line_number, _)
+            | ManifestParseError::UnknownDataType(line_number, _) => *line_number,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        match self {
+            ManifestParseError::MissingField(_, line)
+            | ManifestParseError::InvalidId(_, line)
+            | ManifestParseError::InvalidDataType(_, line)
+            | ManifestParseError::UnknownDataType(_, line) => line,
+        }
+    }
+}
+
+impl StdError for ManifestParseError {}
+
+impl fmt::Display for ManifestParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            ManifestParseError::MissingField(_, _) => "expected `id,data_type,path`",
+            ManifestParseError::InvalidId(_, _) => "invalid id",
+            ManifestParseError::InvalidDataType(_, _) => "invalid data type",
+            ManifestParseError::UnknownDataType(_, _) => "unknown data type",
+        };
+        write!(f, "Manifest error: {} on line {}: {}", reason, self.line_number(), self.line())
+    }
+}
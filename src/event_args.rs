@@ -1,6 +1,10 @@
+use crate::connection::ConnectionState;
 use crate::manifest::Manifest;
 use crate::typed_value::TypedValue;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ReceivedDataArgs {
     pub command_id: i32,
     pub data: TypedValue,
@@ -26,3 +30,17 @@ impl ReceivedManifestArgs {
         }
     }
 }
+
+pub struct ConnectionStateArgs {
+    pub previous_state: ConnectionState,
+    pub state: ConnectionState,
+}
+
+impl ConnectionStateArgs {
+    pub fn new(previous_state: ConnectionState, state: ConnectionState) -> Self {
+        Self {
+            previous_state,
+            state,
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+use crate::aircraft_state::AircraftState;
+use crate::capture::Recorder;
+use crate::commands::Commands;
+use crate::codec::{encode_get_request, encode_run_request, encode_set_request, MANIFEST_ID};
+use crate::connection::ConnectionState;
+use crate::data::{await_values, ConnectionData};
+#[cfg(feature = "discovery")]
+use crate::discovery::InstanceInformation;
+use crate::error::Error;
+use crate::event_args::{ConnectionStateArgs, ReceivedDataArgs, ReceivedManifestArgs};
+use crate::manifest::Manifest;
+#[cfg(feature = "discovery")]
+use crate::manifest_cache::{ManifestCache, ManifestCacheKey};
+use crate::state::{IfState, StateBinding};
+use crate::subscription::{Subscription, Subscriptions};
+use crate::transport::Connector;
+use crate::typed_value::TypedValue;
+
+const READ_BUFFER_SIZE: usize = 4096;
+const DEFAULT_RECONNECT_INITIAL_DELAY: u64 = 500; // ms
+const DEFAULT_RECONNECT_MAX_DELAY: u64 = 30; // s
+const DEFAULT_RECONNECT_MULTIPLIER: f64 = 2.0;
+const DEFAULT_REDISCOVERY_WINDOW: u64 = 3; // s
+
+/// How a spawned connection reconnects after the link to the API drops.
+#[derive(Debug, Clone)]
+pub struct ReconnectOptions {
+    pub enabled: bool,
+    /// Delay before the first attempt, multiplied by `multiplier` after every failed one.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Give up after this many failed attempts, `Some(0)` doesn't try at all. `None` retries forever.
+    pub max_attempts: Option<u32>,
+    /// Look for the instance on the LAN again before every attempt, in case its address changed.
+    /// Only used for connections spawned from an [`InstanceInformation`](crate::discovery::InstanceInformation).
+    pub rediscover: bool,
+    pub rediscovery_window: Duration,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay: Duration::from_millis(DEFAULT_RECONNECT_INITIAL_DELAY),
+            max_delay: Duration::from_secs(DEFAULT_RECONNECT_MAX_DELAY),
+            multiplier: DEFAULT_RECONNECT_MULTIPLIER,
+            max_attempts: None,
+            rediscover: false,
+            rediscovery_window: Duration::from_secs(DEFAULT_REDISCOVERY_WINDOW),
+        }
+    }
+}
+
+impl ReconnectOptions {
+    /// The delay after an attempt made after `delay` failed, at most `max_delay`.
+    /// Multipliers that aren't positive keep the delay as it is.
+    fn next_delay(&self, delay: Duration) -> Duration {
+        if self.multiplier.is_nan() || self.multiplier <= 0.0 {
+            return delay.min(self.max_delay)
+        }
+
+        // an infinite multiplier or a product too large for a duration
+        Duration::try_from_secs_f64(delay.as_secs_f64() * self.multiplier)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+enum SessionEnd {
+    /// Every handle has been dropped.
+    Closed,
+    /// The API closed the connection or it failed.
+    Disconnected,
+}
+
+/// Everything the background task needs to reconnect.
+struct Supervisor<C> {
+    data: Arc<Mutex<ConnectionData>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    requests: mpsc::UnboundedReceiver<Vec<u8>>,
+    connector: C,
+    options: ReconnectOptions,
+}
+
+/// Cloneable handle to a connection driven by a background task.
+/// Created with [`Connection::spawn`](crate::connection::Connection::spawn).
+///
+/// The task reconnects when the link drops (see [`ReconnectOptions`]), re-fetches the manifest
+/// and keeps polling the subscribed states. It stops once every handle has been dropped.
+/// Callbacks run on the background task and may use the handle.
+#[derive(Clone)]
+pub struct ConnectionHandle {
+    data: Arc<Mutex<ConnectionData>>,
+    state: Arc<watch::Sender<ConnectionState>>,
+    requests: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl ConnectionHandle {
+    pub(crate) fn start<C: Connector>(transport: C::Transport, connector: C, options: ReconnectOptions) -> Self {
+        let data = Arc::new(Mutex::new(ConnectionData::new()));
+        let state = Arc::new(watch::Sender::new(ConnectionState::Connected));
+        let (requests, request_receiver) = mpsc::unbounded_channel();
+
+        let subscriptions = lock(&data).subscriptions().clone();
+        let supervisor = Supervisor {
+            data: Arc::clone(&data),
+            state: Arc::clone(&state),
+            requests: request_receiver,
+            connector,
+            options,
+        };
+
+        tokio::spawn(supervisor.run(transport));
+        tokio::spawn(Self::poll_loop(Arc::clone(&data), Arc::clone(&state), requests.downgrade(), subscriptions));
+
+        Self {
+            data,
+            state,
+            requests,
+        }
+    }
+
+    /// A handle without a background task, for driving the connection data some other way.
+    pub(crate) fn detached() -> (Self, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let (requests, request_receiver) = mpsc::unbounded_channel();
+        let handle = Self {
+            data: Arc::new(Mutex::new(ConnectionData::new())),
+            state: Arc::new(watch::Sender::new(ConnectionState::Disconnected)),
+            requests,
+        };
+
+        (handle, request_receiver)
+    }
+
+    /// Use the locked connection data, then call the callbacks that became due once it is unlocked.
+    pub(crate) fn with_data<T>(&self, f: impl FnOnce(&mut ConnectionData) -> T) -> T {
+        with_data(&self.data, f)
+    }
+
+    pub(crate) fn set_state(&self, state: ConnectionState) {
+        let previous_state = self.state.send_replace(state);
+        let change = lock(&self.data).state_changed(previous_state, state);
+        if let Some(change) = change {
+            change.notify();
+        }
+    }
+
+    async fn poll_loop(data: Arc<Mutex<ConnectionData>>, state: Arc<watch::Sender<ConnectionState>>, requests: mpsc::WeakUnboundedSender<Vec<u8>>, subscriptions: Subscriptions) {
+        loop {
+            // stop once every handle is gone or the background task has stopped
+            let requests = match requests.upgrade() {
+                Some(requests) if !requests.is_closed() => requests,
+                _ => break,
+            };
+
+            let due = subscriptions.due_requests(std::time::Instant::now());
+            // polls missed while reconnecting are skipped rather than queued
+            if !due.is_empty() && *state.borrow() == ConnectionState::Connected {
+                let mut data = lock(&data);
+                for state_id in due {
+                    data.expect_response(state_id);
+                    if requests.send(encode_get_request(state_id)).is_err() {
+                        return
+                    }
+                }
+            }
+            drop(requests);
+
+            match subscriptions.next_due() {
+                Some(next_due) => tokio::select! {
+                    _ = tokio::time::sleep_until(next_due.into()) => {},
+                    _ = subscriptions.changed() => {},
+                },
+                None => subscriptions.changed().await,
+            }
+        }
+    }
+
+    /// Request the manifest. It is available through [`ConnectionHandle::manifest`] and the
+    /// manifest callback once received.
+    pub fn get_manifest(&self) -> Result<(), Error> {
+        self.queue_get(MANIFEST_ID)
+    }
+
+    /// Resolve paths with the manifest cached for this instance right away, and revalidate it
+    /// by requesting the live manifest. The cache is updated whenever a received manifest differs.
+    ///
+    /// Returns whether a cached manifest was found; if not, paths can be resolved once the live
+    /// manifest has been received.
+    #[cfg(feature = "discovery")]
+    pub fn use_manifest_cache(&self, cache: ManifestCache, instance: &InstanceInformation) -> Result<bool, Error> {
+        let found = self.with_data(|data| data.set_manifest_cache(cache, ManifestCacheKey::from(instance)));
+        self.get_manifest()?;
+        Ok(found)
+    }
+
+    /// Request the manifest and wait for it to be received.
+    pub async fn fetch_manifest(&self, timeout: Duration) -> Result<Manifest, Error> {
+        self.get_value_id(MANIFEST_ID, timeout).await?;
+        self.manifest()
+    }
+
+    /// Get a copy of the last received manifest.
+    pub fn manifest(&self) -> Result<Manifest, Error> {
+        lock(&self.data).get_manifest().cloned()
+    }
+
+    /// The commands in the last received manifest.
+    pub fn commands(&self) -> Result<Commands, Error> {
+        Ok(Commands::from_manifest(lock(&self.data).get_manifest()?))
+    }
+
+    pub fn get(&self, state_path: &str) -> Result<(), Error> {
+        let state_id = self.resolve_path(state_path)?;
+        self.queue_get(state_id)
+    }
+
+    pub fn set(&self, state_path: &str, value: TypedValue) -> Result<(), Error> {
+        let state_id = self.resolve_path(state_path)?;
+        self.set_id(state_id, value)
+    }
+
+    pub fn run(&self, command_path: &str) -> Result<(), Error> {
+        let command_id = self.resolve_path(command_path)?;
+        self.run_id(command_id)
+    }
+
+    pub fn get_id(&self, state_id: i32) -> Result<(), Error> {
+        self.queue_get(state_id)
+    }
+
+    /// Set a state. The value must match the type of the state in the manifest,
+    /// unless value coercion is enabled and it can be converted without loss.
+    pub fn set_id(&self, state_id: i32, value: TypedValue) -> Result<(), Error> {
+        let value = lock(&self.data).check_set_value(state_id, value)?;
+        self.send_request(encode_set_request(state_id, &value))
+    }
+
+    /// Allow `set` to convert values to the type of the state (i32 to i64 or f64, f32 to f64, bool to i32).
+    /// Disabled by default.
+    pub fn set_value_coercion(&self, enabled: bool) {
+        lock(&self.data).set_coerce_values(enabled);
+    }
+
+    pub fn run_id(&self, command_id: i32) -> Result<(), Error> {
+        self.send_request(encode_run_request(command_id))
+    }
+
+    /// Request the value of a state and wait for the response.
+    pub async fn get_value(&self, state_path: &str, timeout: Duration) -> Result<TypedValue, Error> {
+        let state_id = self.resolve_path(state_path)?;
+        self.get_value_id(state_id, timeout).await
+    }
+
+    /// Same as [`ConnectionHandle::get_value`], but takes the state id directly.
+    pub async fn get_value_id(&self, state_id: i32, timeout: Duration) -> Result<TypedValue, Error> {
+        let receiver = {
+            let mut data = lock(&self.data);
+            let receiver = data.expect_value(state_id);
+            // send while holding the lock so responses stay in the order they are expected in
+            self.send_request(encode_get_request(state_id))?;
+            receiver
+        };
+
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Error::Disconnected()),
+            Err(_) => Err(Error::Timeout(state_id)),
+        }
+    }
+
+    /// Fetch a snapshot of the user aircraft, requesting all of its states in one batch.
+    /// States the current aircraft doesn't have in its manifest are left empty.
+    pub async fn poll_aircraft_state(&self, timeout: Duration) -> Result<AircraftState, Error> {
+        let (paths, state_ids): (Vec<&str>, Vec<i32>) = AircraftState::state_ids(lock(&self.data).get_manifest()?).into_iter().unzip();
+        let values = self.get_values_id(&state_ids, timeout).await?;
+        Ok(AircraftState::from_values(&paths, &values))
+    }
+
+    /// Resolve the states of an [`IfState`] struct in the current manifest and check their types.
+    pub fn bind_state<T: IfState>(&self) -> Result<StateBinding<T>, Error> {
+        StateBinding::bind(lock(&self.data).get_manifest()?)
+    }
+
+    /// Fetch an [`IfState`] struct, requesting all of its states in one batch.
+    pub async fn fetch_state<T: IfState>(&self, binding: &StateBinding<T>, timeout: Duration) -> Result<T, Error> {
+        let values = self.get_values_id(&binding.bound_ids(), timeout).await?;
+        binding.build(values)
+    }
+
+    /// Fetch every member of an indexed group like `aircraft/0/systems/engines/{}/n1`,
+    /// requesting them in one batch. The values are in index order.
+    pub async fn get_indexed_values(&self, template: &str, timeout: Duration) -> Result<Vec<TypedValue>, Error> {
+        let state_ids = lock(&self.data).get_manifest()?.indexed_group(template)?.state_ids();
+        self.get_values_id(&state_ids, timeout).await
+    }
+
+    /// Request the values of several states at once and wait for all of them.
+    /// The timeout covers the whole batch.
+    pub async fn get_values_id(&self, state_ids: &[i32], timeout: Duration) -> Result<Vec<TypedValue>, Error> {
+        let requests = {
+            let mut data = lock(&self.data);
+            let mut requests = Vec::with_capacity(state_ids.len());
+            for state_id in state_ids {
+                requests.push((*state_id, data.expect_value(*state_id)));
+                // send while holding the lock so responses stay in the order they are expected in
+                self.send_request(encode_get_request(*state_id))?;
+            }
+            requests
+        };
+
+        await_values(requests, timeout).await
+    }
+
+    /// Poll a state at the given interval, at least 10 ms, while the returned subscription is alive.
+    ///
+    /// Subscribers of the same state share its requests: it is polled at the shortest of their intervals.
+    pub fn subscribe(&self, state_path: &str, interval: Duration) -> Result<Subscription, Error> {
+        let data = lock(&self.data);
+        let state_id = data.get_manifest()?.get_entry_by_path(state_path)?.id;
+        Ok(data.subscriptions().subscribe(state_id, Some(state_path), interval))
+    }
+
+    pub fn subscribe_id(&self, state_id: i32, interval: Duration) -> Subscription {
+        lock(&self.data).subscriptions().subscribe(state_id, None, interval)
+    }
+
+    /// Record the bytes going both ways to a capture, see [`Recorder`].
+    /// Replaces the recording in progress, if any.
+    pub fn start_recording(&self, recorder: Recorder) {
+        lock(&self.data).set_recorder(Some(recorder));
+    }
+
+    /// Stop recording and flush the capture.
+    pub fn stop_recording(&self) -> Result<(), Error> {
+        match lock(&self.data).set_recorder(None) {
+            Some(mut recorder) => Ok(recorder.flush()?),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the link to the API is currently up.
+    pub fn is_connected(&self) -> bool {
+        self.get_connection_state() == ConnectionState::Connected
+    }
+
+    pub fn get_connection_state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Get a receiver that is notified whenever the connection state changes.
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    pub fn on_state_change<F: Fn(ConnectionStateArgs) + Send + 'static>(&self, func: Option<F>) {
+        let mut data = lock(&self.data);
+        match func {
+            Some(f) => {
+                data.set_state_changed_callback::<F>(Some(Box::new(f)));
+            },
+            None => {
+                data.set_state_changed_callback::<F>(None);
+            },
+        }
+    }
+
+    pub fn on_receive_data<F: Fn(ReceivedDataArgs) + Send + 'static>(&self, func: Option<F>) {
+        let mut data = lock(&self.data);
+        match func {
+            Some(f) => {
+                data.set_received_data_callback::<F>(Some(Box::new(f)));
+            },
+            None => {
+                data.set_received_data_callback::<F>(None);
+            },
+        }
+    }
+
+    pub fn on_receive_manifest<F: Fn(ReceivedManifestArgs) + Send + 'static>(&self, func: Option<F>) {
+        let mut data = lock(&self.data);
+        match func {
+            Some(f) => {
+                data.set_received_manifest_callback::<F>(Some(Box::new(f)));
+            },
+            None => {
+                data.set_received_manifest_callback::<F>(None);
+            },
+        }
+    }
+
+    fn resolve_path(&self, path: &str) -> Result<i32, Error> {
+        let data = lock(&self.data);
+        let entry = data.get_manifest()?.get_entry_by_path(path)?;
+        Ok(entry.id)
+    }
+
+    fn queue_get(&self, state_id: i32) -> Result<(), Error> {
+        let mut data = lock(&self.data);
+        data.expect_response(state_id);
+        self.send_request(encode_get_request(state_id))
+    }
+
+    fn send_request(&self, request: Vec<u8>) -> Result<(), Error> {
+        // requests made while reconnecting would be stale by the time they are sent
+        if !self.is_connected() {
+            return Err(Error::Disconnected())
+        }
+        self.requests.send(request).map_err(|_| Error::Disconnected())
+    }
+}
+
+impl<C: Connector> Supervisor<C> {
+    async fn run(mut self, mut transport: C::Transport) {
+        loop {
+            self.set_state(ConnectionState::Connected);
+            let end = self.run_session(transport).await;
+
+            // dropping the waiters lets pending requests fail instead of timing out
+            lock(&self.data).clear_expected_responses();
+
+            if let SessionEnd::Closed = end {
+                break
+            }
+            if !self.options.enabled {
+                break
+            }
+
+            self.set_state(ConnectionState::Connecting);
+            transport = match self.reconnect().await {
+                Some(transport) => transport,
+                None => break,
+            };
+
+            // requests that were queued when the link dropped are stale by now
+            while self.requests.try_recv().is_ok() {}
+        }
+
+        self.set_state(ConnectionState::Disconnected);
+        // stops the poll task
+        self.requests.close();
+        lock(&self.data).subscriptions().wake();
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        let previous_state = self.state.send_replace(state);
+        // the callback may use the handle, so it's called without holding the lock
+        let change = lock(&self.data).state_changed(previous_state, state);
+        if let Some(change) = change {
+            change.notify();
+        }
+    }
+
+    async fn run_session(&mut self, transport: C::Transport) -> SessionEnd {
+        let (mut read_half, mut write_half) = tokio::io::split(transport);
+
+        // the manifest may have changed while disconnected, e.g. with a different aircraft
+        let refetch_manifest = {
+            let mut data = lock(&self.data);
+            let had_manifest = data.get_manifest().is_ok();
+            if had_manifest {
+                data.expect_response(MANIFEST_ID);
+                data.request_sent(&encode_get_request(MANIFEST_ID));
+            }
+            had_manifest
+        };
+        if refetch_manifest {
+            let request = encode_get_request(MANIFEST_ID);
+            if write_half.write_all(&request).await.is_err() || write_half.flush().await.is_err() {
+                return SessionEnd::Disconnected
+            }
+        }
+
+        let data = &self.data;
+        let read = async move {
+            let mut buf = [0u8; READ_BUFFER_SIZE];
+            loop {
+                match read_half.read(&mut buf).await {
+                    Ok(len) if len > 0 => {
+                        if let Err(error) = with_data(data, |data| data.receive_bytes(&buf[0..len])) {
+                            log::error!("Failed to read data from the API, closing the connection: {}", error);
+                            return
+                        }
+                    },
+                    _ => return,
+                }
+            }
+        };
+
+        let requests = &mut self.requests;
+        let write = async move {
+            while let Some(mut request) = requests.recv().await {
+                // write whatever is queued, then flush for transports that buffer
+                loop {
+                    lock(data).request_sent(&request);
+                    if write_half.write_all(&request).await.is_err() {
+                        return SessionEnd::Disconnected
+                    }
+                    request = match requests.try_recv() {
+                        Ok(request) => request,
+                        Err(_) => break,
+                    };
+                }
+                if write_half.flush().await.is_err() {
+                    return SessionEnd::Disconnected
+                }
+            }
+            SessionEnd::Closed
+        };
+
+        tokio::select! {
+            _ = read => SessionEnd::Disconnected,
+            end = write => end,
+        }
+    }
+
+    async fn reconnect(&mut self) -> Option<C::Transport> {
+        let mut delay = self.options.initial_delay;
+        let mut attempts = 0;
+        loop {
+            if self.options.max_attempts.is_some_and(|max_attempts| attempts >= max_attempts) {
+                return None
+            }
+
+            tokio::time::sleep(delay).await;
+            // nobody is left to use the connection
+            if self.requests.is_closed() {
+                return None
+            }
+
+            if let Ok(transport) = self.connector.connect().await {
+                return Some(transport)
+            }
+
+            attempts += 1;
+            delay = self.options.next_delay(delay);
+        }
+    }
+}
+
+/// Lock the connection state, recovering it if a callback panicked while it was locked.
+fn lock(data: &Mutex<ConnectionData>) -> MutexGuard<'_, ConnectionData> {
+    data.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Use the locked connection state, then call the callbacks that became due. They are called
+/// once the lock is released, so they may use the handle.
+fn with_data<T>(data: &Mutex<ConnectionData>, f: impl FnOnce(&mut ConnectionData) -> T) -> T {
+    let (result, notifications) = {
+        let mut data = lock(data);
+        let result = f(&mut data);
+        (result, data.take_notifications())
+    };
+
+    for notification in notifications {
+        notification.notify();
+    }
+    result
+}
@@ -1,10 +1,34 @@
+pub mod aircraft_state;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "async")]
+pub mod capture;
+pub mod codec;
+pub mod commands;
+#[cfg(feature = "async")]
 pub mod connection;
+#[cfg(feature = "async")]
 pub mod data;
+#[cfg(feature = "discovery")]
+pub mod discovery;
 pub mod manifest;
+pub mod manifest_cache;
+pub mod state;
+#[cfg(feature = "async")]
+pub mod subscription;
+#[cfg(feature = "async")]
+pub mod transport;
 pub mod typed_value;
 pub mod error;
+#[cfg(feature = "async")]
+pub mod handle;
+#[cfg(feature = "async")]
 pub mod event_args;
 pub mod helpers;
+#[cfg(feature = "async")]
+pub mod testing;
+
+pub use error::{Error, Result};
 
 pub const UDP_PORT: u32 = 15000;
 pub const TCP_PORT_V2: u32 = 10112;
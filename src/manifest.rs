@@ -1,46 +1,126 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
-use crate::error::{ManifestError};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, ManifestParseError};
 use crate::typed_value::Type;
+#[cfg(any(feature = "async", feature = "blocking"))]
+use crate::typed_value::TypedValue;
 
-#[derive(Debug, Clone)]
+#[cfg(feature = "regex")]
+pub use regex::Regex;
+
+/// Stands for the index in the path of an [`IndexedGroup`].
+const INDEX_PLACEHOLDER: &str = "{}";
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Entry {
     pub id: i32,
     pub data_type: i32,
     pub string: String,
 }
 
-#[derive(Clone)]
+impl Entry {
+    /// Commands are listed with a data type of -1 under `commands/`.
+    pub fn is_command(&self) -> bool {
+        self.data_type == -1 || self.string.starts_with("commands/")
+    }
+}
+
+/// Serialized as the list of its entries.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(from = "Vec<Entry>", into = "Vec<Entry>"))]
 pub struct Manifest {
     entries: Vec<Entry>,
     entries_by_path: HashMap<String, Entry>,
+    // indices into `entries`, looked up for every received frame
+    entries_by_id: HashMap<i32, usize>,
+    // indices into `entries` sorted by path, so entries sharing a prefix are next to each other
+    sorted_by_path: Vec<usize>,
 }
 
 impl Manifest {
-    pub(crate) fn from_str(manifest_str: &str) -> Self {
-        let mut entries = Vec::<Entry>::new();
-        for line in manifest_str.split('\n') {
-            if line.trim().is_empty() { continue }
-            let mut line_split = line.split(',');
+    /// Parse a manifest in the `id,data_type,path` line format sent by the API.
+    ///
+    /// Everything after the second comma belongs to the path, so paths may contain commas.
+    /// Entries with a data type this crate doesn't know are kept as they are.
+    pub fn parse(manifest_str: &str) -> Result<Self, ManifestParseError> {
+        let entries = Self::parse_entries(manifest_str)?;
+        Ok(Self::from_entries(entries))
+    }
 
-            let id_str = line_split.next().unwrap();
-            let type_str = line_split.next().unwrap();
-            let string = line_split.next().unwrap();
+    /// Same as [`Manifest::parse`], but leaves out entries with an unknown data type
+    /// and returns them as warnings instead.
+    pub fn parse_known_types(manifest_str: &str) -> Result<(Self, Vec<ManifestParseError>), ManifestParseError> {
+        let mut warnings = Vec::new();
+        let mut entries = Vec::new();
+        for (line_number, entry) in Self::parse_entries_with_lines(manifest_str)? {
+            if Type::from_id(entry.data_type).is_some() {
+                entries.push(entry);
+            } else {
+                warnings.push(ManifestParseError::UnknownDataType(line_number, Self::entry_line(&entry)));
+            }
+        }
 
-            entries.push(Entry {
-                id: i32::from_str(id_str).unwrap(),
-                data_type: i32::from_str(type_str).unwrap(),
-                string: string.to_string()
-            });
+        Ok((Self::from_entries(entries), warnings))
+    }
+
+    /// Build a manifest from a list of entries.
+    pub fn from_entries(entries: Vec<Entry>) -> Self {
+        let mut entries_by_id = HashMap::with_capacity(entries.len());
+        for (index, entry) in entries.iter().enumerate() {
+            // the first entry with an id wins, like it did with a linear scan
+            entries_by_id.entry(entry.id).or_insert(index);
         }
 
+        let mut sorted_by_path: Vec<usize> = (0..entries.len()).collect();
+        sorted_by_path.sort_by(|a, b| entries[*a].string.cmp(&entries[*b].string));
+
         Self {
-            entries: entries.clone(),
             entries_by_path: Self::construct_entries_by_path(&entries),
+            entries_by_id,
+            sorted_by_path,
+            entries,
+        }
+    }
+
+    fn parse_entries(manifest_str: &str) -> Result<Vec<Entry>, ManifestParseError> {
+        Ok(Self::parse_entries_with_lines(manifest_str)?.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    fn parse_entries_with_lines(manifest_str: &str) -> Result<Vec<(usize, Entry)>, ManifestParseError> {
+        let mut entries = Vec::new();
+        for (index, line) in manifest_str.split('\n').enumerate() {
+            let line_number = index + 1;
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() { continue }
+
+            let mut line_split = line.splitn(3, ',');
+            let (id_str, type_str, string) = match (line_split.next(), line_split.next(), line_split.next()) {
+                (Some(id_str), Some(type_str), Some(string)) => (id_str, type_str, string),
+                _ => return Err(ManifestParseError::MissingField(line_number, line.to_string())),
+            };
+
+            let id = i32::from_str(id_str.trim()).map_err(|_| ManifestParseError::InvalidId(line_number, line.to_string()))?;
+            let data_type = i32::from_str(type_str.trim()).map_err(|_| ManifestParseError::InvalidDataType(line_number, line.to_string()))?;
+
+            entries.push((line_number, Entry {
+                id,
+                data_type,
+                string: string.to_string()
+            }));
         }
+
+        Ok(entries)
     }
 
-    pub fn construct_entries_by_path(entries: &Vec<Entry>) -> HashMap<String, Entry> {
+    fn entry_line(entry: &Entry) -> String {
+        format!("{},{},{}", entry.id, entry.data_type, entry.string)
+    }
+
+    pub fn construct_entries_by_path(entries: &[Entry]) -> HashMap<String, Entry> {
         let mut result = HashMap::new();
         for entry in entries {
             result.insert(entry.string.clone(), entry.clone());
@@ -49,56 +129,354 @@ impl Manifest {
         result
     }
 
-    pub(crate) fn get_entry_by_id(&self, id: &i32) -> Result<&Entry, ManifestError> {
-        for entry in &self.entries {
-            if &entry.id == id {
-                return Ok(entry)
-            }
+    pub(crate) fn get_entry_by_id(&self, id: &i32) -> Result<&Entry, Error> {
+        self.entries_by_id.get(id).map(|index| &self.entries[*index]).ok_or(Error::NoSuchEntryId(*id))
+    }
+
+    pub(crate) fn get_entry_by_path(&self, path: &str) -> Result<&Entry, Error> {
+        self.entries_by_path.get(path).ok_or_else(|| Error::NoSuchEntryPath(path.to_string()))
+    }
+
+    /// Entries whose path starts with the prefix, sorted by path.
+    pub fn get_entries_with_prefix(&self, prefix: &str) -> Vec<&Entry> {
+        self.sorted_with_prefix(prefix).iter().map(|index| &self.entries[*index]).collect()
+    }
+
+    /// The root of the tree formed by splitting the paths on `/`.
+    pub fn root(&self) -> ManifestNode<'_> {
+        ManifestNode { manifest: self, path: "" }
+    }
+
+    /// The node at this path, if it is an entry or has entries below it.
+    pub fn node(&self, path: &str) -> Option<ManifestNode<'_>> {
+        let path = path.trim_end_matches('/');
+        if path.is_empty() {
+            return Some(self.root())
         }
 
-        Err(ManifestError::NoSuchEntryId(*id))
+        self.sorted_with_prefix(path).iter()
+            .map(|index| &self.entries[*index].string)
+            .find(|entry_path| entry_path.len() == path.len() || entry_path.as_bytes()[path.len()] == b'/')
+            .map(|entry_path| ManifestNode { manifest: self, path: &entry_path[..path.len()] })
     }
 
-    pub(crate) fn get_entry_by_path(&self, path: &str) -> Result<&Entry, ManifestError> {
-        let entry = self.entries_by_path.get(path);
+    /// Entries whose path matches a glob pattern, sorted by path.
+    ///
+    /// `*` matches any part of a path segment, `?` a single character in a segment
+    /// and `**` any number of segments, e.g. `aircraft/0/systems/**/state`.
+    pub fn find_glob(&self, pattern: &str) -> Vec<&Entry> {
+        let pattern: Vec<&str> = pattern.split('/').collect();
+        self.sorted_entries()
+            .filter(|entry| glob_match_segments(&pattern, &entry.string.split('/').collect::<Vec<_>>()))
+            .collect()
+    }
+
+    /// Entries whose path matches a regular expression, sorted by path.
+    #[cfg(feature = "regex")]
+    pub fn find_regex(&self, regex: &Regex) -> Vec<&Entry> {
+        self.sorted_entries().filter(|entry| regex.is_match(&entry.string)).collect()
+    }
+
+    /// The members of an indexed group, given as a path with `{}` in place of the index,
+    /// e.g. `aircraft/0/systems/engines/{}/n1`.
+    pub fn indexed_group(&self, template: &str) -> Result<IndexedGroup<'_>, Error> {
+        let no_group = || Error::NoSuchEntryPath(template.to_string());
+        let (prefix, suffix) = template.split_once(INDEX_PLACEHOLDER).ok_or_else(no_group)?;
+        if suffix.contains(INDEX_PLACEHOLDER) || !(prefix.is_empty() || prefix.ends_with('/')) || !(suffix.is_empty() || suffix.starts_with('/')) {
+            return Err(no_group())
+        }
 
-        if entry.is_some() {
-            Ok(entry.unwrap())
-        } else {
-            Err(ManifestError::NoSuchEntryPath(path.to_string()))
+        let mut members: Vec<(u32, &Entry)> = self.sorted_with_prefix(prefix).iter()
+            .map(|index| &self.entries[*index])
+            .filter_map(|entry| {
+                let index = entry.string[prefix.len()..].strip_suffix(suffix)?;
+                Some((parse_index(index)?, entry))
+            })
+            .collect();
+        if members.is_empty() {
+            return Err(no_group())
         }
+        members.sort_by_key(|(index, _)| *index);
+
+        Ok(IndexedGroup { template: template.to_string(), members })
     }
 
-    pub fn get_entries_with_prefix(&self, prefix: &str) -> Vec<&Entry> {
-        let mut result = Vec::new();
-        for entry in &self.entries_by_path {
-            if entry.0.starts_with(prefix) {
-                result.push(entry.1);
+    /// Every array in the manifest, i.e. every path followed by a numeric segment,
+    /// with the number of distinct indices it has. Sorted by path.
+    ///
+    /// For example `aircraft/0/systems/engines/0/n1` and `aircraft/0/systems/engines/1/n1`
+    /// make `aircraft` an array of 1 and `aircraft/0/systems/engines` an array of 2.
+    pub fn arrays(&self) -> Vec<(&str, usize)> {
+        let mut arrays: BTreeMap<&str, BTreeSet<u32>> = BTreeMap::new();
+        for entry in &self.entries {
+            let mut offset = 0;
+            for segment in entry.string.split('/') {
+                if offset > 0 {
+                    if let Some(index) = parse_index(segment) {
+                        arrays.entry(&entry.string[..offset - 1]).or_default().insert(index);
+                    }
+                }
+                offset += segment.len() + 1;
             }
         }
 
-        result
+        arrays.into_iter().map(|(path, indices)| (path, indices.len())).collect()
+    }
+
+    /// Compare with a newer manifest, matching entries by path.
+    pub fn diff(&self, other: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+        for entry in self.sorted_entries() {
+            match other.entries_by_path.get(&entry.string) {
+                None => diff.removed.push(entry.clone()),
+                Some(new) => {
+                    let change = || EntryChange { old: entry.clone(), new: new.clone() };
+                    if new.data_type != entry.data_type {
+                        diff.retyped.push(change());
+                    }
+                    if new.id != entry.id {
+                        diff.renumbered.push(change());
+                    }
+                },
+            }
+        }
+        diff.added = other.sorted_entries()
+            .filter(|entry| !self.entries_by_path.contains_key(&entry.string))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    fn sorted_entries(&self) -> impl Iterator<Item = &Entry> {
+        self.sorted_by_path.iter().map(|index| &self.entries[*index])
+    }
+
+    /// The indices of the entries whose path starts with the prefix, in path order.
+    fn sorted_with_prefix(&self, prefix: &str) -> &[usize] {
+        let start = self.sorted_by_path.partition_point(|index| self.entries[*index].string.as_str() < prefix);
+        let len = self.sorted_by_path[start..].partition_point(|index| self.entries[*index].string.starts_with(prefix));
+        &self.sorted_by_path[start..start + len]
     }
 
-    pub(crate) fn get_data_type_for_id(&self, id: &i32) -> Result<Type, ManifestError> {
-        let entry = self.get_entry_by_id(id);
-        if entry.is_err() {
-            return Err(ManifestError::NoSuchEntryId(*id))
+    pub(crate) fn get_data_type_for_id(&self, id: &i32) -> Result<Type, Error> {
+        let act_entry = self.get_entry_by_id(id)?;
+        Type::from_id(act_entry.data_type).ok_or(Error::WrongDataType(act_entry.data_type))
+    }
+
+    /// Check a value before setting a state: it must match the type of the state, unless
+    /// `coerce` allows converting it without loss.
+    #[cfg(any(feature = "async", feature = "blocking"))]
+    pub(crate) fn check_set_value(&self, state_id: i32, value: TypedValue, coerce: bool) -> Result<TypedValue, Error> {
+        if self.get_entry_by_id(&state_id)?.is_command() {
+            return Err(Error::NotSettable(state_id))
         }
 
-        let act_entry = entry.unwrap();
-        match act_entry.data_type {
-            0 => Ok(Type::Boolean),
-            1 => Ok(Type::Integer32),
-            2 => Ok(Type::Float),
-            3 => Ok(Type::Double),
-            4 => Ok(Type::String),
-            5 => Ok(Type::Long),
-            _ => Err(ManifestError::WrongDataType(act_entry.data_type))
+        let data_type = self.get_data_type_for_id(&state_id)?;
+        if value.get_type() == data_type {
+            return Ok(value)
         }
+
+        let coerced = if coerce { value.coerce_to(&data_type) } else { None };
+        coerced.ok_or_else(|| Error::TypeMismatch(state_id, data_type, value.get_type()))
     }
 
     pub fn get_number_of_entries(&self) -> usize {
         self.entries.len()
     }
+
+    pub fn get_entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+/// The differences between two manifests, each list sorted by path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestDiff {
+    pub added: Vec<Entry>,
+    pub removed: Vec<Entry>,
+    /// Entries whose data type changed.
+    pub retyped: Vec<EntryChange>,
+    /// Entries whose id changed. An entry can be both re-typed and re-numbered.
+    pub renumbered: Vec<EntryChange>,
+}
+
+/// An entry present in both manifests of a [`ManifestDiff`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryChange {
+    pub old: Entry,
+    pub new: Entry,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.retyped.is_empty() && self.renumbered.is_empty()
+    }
+
+    /// Whether code written against the old manifest may break: anything but added entries.
+    pub fn is_breaking(&self) -> bool {
+        !self.removed.is_empty() || !self.retyped.is_empty() || !self.renumbered.is_empty()
+    }
+}
+
+/// One line per change: `+` added, `-` removed, `~` re-typed and `#` re-numbered.
+impl Display for ManifestDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.added {
+            writeln!(f, "+ {} (id {}, type {})", entry.string, entry.id, entry.data_type)?;
+        }
+        for entry in &self.removed {
+            writeln!(f, "- {} (id {}, type {})", entry.string, entry.id, entry.data_type)?;
+        }
+        for change in &self.retyped {
+            writeln!(f, "~ {}: type {} -> {}", change.new.string, change.old.data_type, change.new.data_type)?;
+        }
+        for change in &self.renumbered {
+            writeln!(f, "# {}: id {} -> {}", change.new.string, change.old.id, change.new.id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A node in the tree formed by the manifest paths: either an entry, a group of entries
+/// like `aircraft/0/systems`, or both.
+#[derive(Debug, Clone, Copy)]
+pub struct ManifestNode<'a> {
+    manifest: &'a Manifest,
+    path: &'a str,
+}
+
+impl<'a> ManifestNode<'a> {
+    /// The full path of the node, empty for the root.
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+
+    /// The last segment of the path.
+    pub fn name(&self) -> &'a str {
+        self.path.rsplit('/').next().unwrap_or(self.path)
+    }
+
+    /// The entry at exactly this path, if there is one.
+    pub fn entry(&self) -> Option<&'a Entry> {
+        self.manifest.get_entry_by_path(self.path).ok()
+    }
+
+    /// The nodes one level below this one, sorted by name.
+    pub fn children(&self) -> Vec<ManifestNode<'a>> {
+        let prefix = if self.path.is_empty() { String::new() } else { format!("{}/", self.path) };
+
+        let mut children = BTreeMap::new();
+        for index in self.manifest.sorted_with_prefix(&prefix) {
+            let entry_path = self.manifest.entries[*index].string.as_str();
+            let name = entry_path[prefix.len()..].split('/').next().unwrap_or_default();
+            children.entry(name).or_insert(&entry_path[..prefix.len() + name.len()]);
+        }
+
+        children.into_values().map(|path| ManifestNode { manifest: self.manifest, path }).collect()
+    }
+
+    /// The numeric names of the children, sorted, e.g. the engine numbers of `aircraft/0/systems/engines`.
+    pub fn indices(&self) -> Vec<u32> {
+        let mut indices: Vec<u32> = self.children().iter().filter_map(|child| parse_index(child.name())).collect();
+        indices.sort_unstable();
+        indices
+    }
+
+    /// This entry and every entry below it, sorted by path.
+    pub fn entries(&self) -> Vec<&'a Entry> {
+        self.manifest.sorted_with_prefix(self.path).iter()
+            .map(|index| &self.manifest.entries[*index])
+            .filter(|entry| self.path.is_empty() || entry.string.len() == self.path.len() || entry.string.as_bytes()[self.path.len()] == b'/')
+            .collect()
+    }
+}
+
+/// The entries of an indexed group such as the N1 of every engine, sorted by index.
+#[derive(Debug, Clone)]
+pub struct IndexedGroup<'a> {
+    template: String,
+    members: Vec<(u32, &'a Entry)>,
+}
+
+impl<'a> IndexedGroup<'a> {
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// The number of members, e.g. the number of engines.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn indices(&self) -> Vec<u32> {
+        self.members.iter().map(|(index, _)| *index).collect()
+    }
+
+    pub fn get(&self, index: u32) -> Option<&'a Entry> {
+        self.members.iter().find(|(member_index, _)| *member_index == index).map(|(_, entry)| *entry)
+    }
+
+    pub fn entries(&self) -> Vec<&'a Entry> {
+        self.members.iter().map(|(_, entry)| *entry).collect()
+    }
+
+    pub fn state_ids(&self) -> Vec<i32> {
+        self.members.iter().map(|(_, entry)| entry.id).collect()
+    }
+}
+
+fn parse_index(segment: &str) -> Option<u32> {
+    if segment.is_empty() || !segment.bytes().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+    segment.parse().ok()
+}
+
+fn glob_match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| glob_match_segments(rest, &path[skip..])),
+        Some((segment_pattern, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => glob_match_segment(segment_pattern.as_bytes(), segment.as_bytes()) && glob_match_segments(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+fn glob_match_segment(pattern: &[u8], segment: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => segment.is_empty(),
+        Some((b'*', rest)) => (0..=segment.len()).any(|skip| glob_match_segment(rest, &segment[skip..])),
+        Some((b'?', rest)) => !segment.is_empty() && glob_match_segment(rest, &segment[1..]),
+        Some((c, rest)) => segment.first() == Some(c) && glob_match_segment(rest, &segment[1..]),
+    }
+}
+
+/// Serializes the manifest back into the format it is parsed from, keeping the entry order.
+impl Display for Manifest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", Self::entry_line(entry))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Vec<Entry>> for Manifest {
+    fn from(entries: Vec<Entry>) -> Self {
+        Self::from_entries(entries)
+    }
+}
+
+impl From<Manifest> for Vec<Entry> {
+    fn from(manifest: Manifest) -> Self {
+        manifest.entries
+    }
 }
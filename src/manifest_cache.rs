@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+#[cfg(feature = "discovery")]
+use crate::discovery::InstanceInformation;
+use crate::error::Error;
+use crate::manifest::Manifest;
+
+const CACHE_FILE_EXTENSION: &str = "manifest";
+
+/// Manifests saved on disk, one per Infinite Flight version and aircraft.
+///
+/// The manifest only changes with the app version and the aircraft being flown, so a cached one
+/// lets paths be resolved right after connecting instead of waiting for the manifest to transfer.
+/// See [`ConnectionHandle::use_manifest_cache`](crate::handle::ConnectionHandle::use_manifest_cache).
+#[derive(Debug, Clone)]
+pub struct ManifestCache {
+    dir: PathBuf,
+}
+
+/// Identifies the manifest of an instance in a [`ManifestCache`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ManifestCacheKey {
+    pub version: String,
+    pub aircraft: String,
+}
+
+impl ManifestCacheKey {
+    pub fn new(version: &str, aircraft: &str) -> Self {
+        Self {
+            version: version.to_string(),
+            aircraft: aircraft.to_string(),
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!("{}-{}.{}", sanitize(&self.version), sanitize(&self.aircraft), CACHE_FILE_EXTENSION)
+    }
+}
+
+#[cfg(feature = "discovery")]
+impl From<&InstanceInformation> for ManifestCacheKey {
+    fn from(instance: &InstanceInformation) -> Self {
+        Self::new(&instance.version, &instance.aircraft)
+    }
+}
+
+impl ManifestCache {
+    /// Use a directory for the cache. It is created when the first manifest is stored.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cached manifest for the key, if there is one that can still be parsed.
+    pub fn load(&self, key: &ManifestCacheKey) -> Option<Manifest> {
+        let manifest_str = std::fs::read_to_string(self.path(key)).ok()?;
+        Manifest::parse(&manifest_str).ok()
+    }
+
+    pub fn store(&self, key: &ManifestCacheKey, manifest: &Manifest) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        // write to a temporary file first, so a reader never sees half a manifest
+        let path = self.path(key);
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, manifest.to_string())?;
+        std::fs::rename(temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &ManifestCacheKey) -> Result<(), Error> {
+        match std::fs::remove_file(self.path(key)) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn path(&self, key: &ManifestCacheKey) -> PathBuf {
+        self.dir.join(key.file_name())
+    }
+}
+
+/// Keep file names portable, aircraft names may contain anything.
+/// Every other byte is percent-encoded, so distinct keys never share a file.
+fn sanitize(part: &str) -> String {
+    let mut name = String::with_capacity(part.len());
+    for byte in part.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'.' || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    name
+}
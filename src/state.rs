@@ -0,0 +1,153 @@
+use std::marker::PhantomData;
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::typed_value::{Type, TypedValue};
+
+#[cfg(feature = "derive")]
+pub use ifconnect_derive::IfState;
+
+/// A struct filled with the values of a set of states, usually derived with `#[derive(IfState)]`
+/// (the `derive` feature).
+///
+/// Fetch it with [`ConnectionHandle::fetch_state`](crate::handle::ConnectionHandle::fetch_state)
+/// after binding it to the manifest with [`StateBinding::bind`].
+pub trait IfState: Sized {
+    /// The fields of the struct and the states they are read from, in declaration order.
+    fn fields() -> &'static [StateField];
+
+    /// Build the struct from one value per field, `None` for optional states missing from the manifest.
+    /// Returns `None` if a value doesn't fit its field.
+    fn from_values(values: Vec<Option<TypedValue>>) -> Option<Self>;
+}
+
+/// A field of an [`IfState`] struct.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateField {
+    pub name: &'static str,
+    pub path: &'static str,
+    pub data_type: Type,
+    /// Whether binding fails if the state is missing from the manifest.
+    pub required: bool,
+}
+
+/// Types that can be the field of an [`IfState`] struct.
+/// `Option` fields may be missing from the manifest.
+pub trait StateValue: Sized {
+    const DATA_TYPE: Type;
+    const REQUIRED: bool = true;
+
+    fn from_value(value: Option<TypedValue>) -> Option<Self>;
+}
+
+macro_rules! impl_state_value {
+    ($ty:ty, $variant:ident) => {
+        impl StateValue for $ty {
+            const DATA_TYPE: Type = Type::$variant;
+
+            fn from_value(value: Option<TypedValue>) -> Option<Self> {
+                match value {
+                    Some(TypedValue::$variant(val)) => Some(val),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_state_value!(bool, Boolean);
+impl_state_value!(i32, Integer32);
+impl_state_value!(f32, Float);
+impl_state_value!(f64, Double);
+impl_state_value!(String, String);
+impl_state_value!(i64, Long);
+
+impl<T: StateValue> StateValue for Option<T> {
+    const DATA_TYPE: Type = T::DATA_TYPE;
+    const REQUIRED: bool = false;
+
+    fn from_value(value: Option<TypedValue>) -> Option<Self> {
+        match value {
+            Some(value) => T::from_value(Some(value)).map(Some),
+            None => Some(None),
+        }
+    }
+}
+
+/// The ids of the fields of an [`IfState`] struct in a particular manifest.
+///
+/// Binding checks the type of every field against its entry, so a struct that doesn't match the
+/// aircraft is rejected before anything is requested. Bind again after the manifest changes.
+pub struct StateBinding<T> {
+    state_ids: Vec<Option<i32>>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for StateBinding<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state_ids: self.state_ids.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: IfState> StateBinding<T> {
+    pub fn bind(manifest: &Manifest) -> Result<Self, Error> {
+        let mut state_ids = Vec::with_capacity(T::fields().len());
+        for field in T::fields() {
+            let entry = match manifest.get_entry_by_path(field.path) {
+                Ok(entry) => entry,
+                Err(_) if !field.required => {
+                    state_ids.push(None);
+                    continue
+                },
+                Err(error) => return Err(error),
+            };
+
+            let data_type = manifest.get_data_type_for_id(&entry.id)?;
+            if data_type != field.data_type {
+                return Err(Error::TypeMismatch(entry.id, data_type, field.data_type))
+            }
+            state_ids.push(Some(entry.id));
+        }
+
+        Ok(Self { state_ids, marker: PhantomData })
+    }
+
+    /// The id of every field, `None` for optional fields missing from the manifest.
+    pub fn state_ids(&self) -> &[Option<i32>] {
+        &self.state_ids
+    }
+
+    /// The ids of the fields that are in the manifest, to be fetched in one batch.
+    #[cfg(feature = "async")]
+    pub(crate) fn bound_ids(&self) -> Vec<i32> {
+        self.state_ids.iter().flatten().copied().collect()
+    }
+
+    /// Build the struct from the values of [`StateBinding::bound_ids`].
+    #[cfg(feature = "async")]
+    pub(crate) fn build(&self, values: Vec<TypedValue>) -> Result<T, Error> {
+        let mut values = values.into_iter();
+        let mut field_values = Vec::with_capacity(self.state_ids.len());
+
+        for (field, state_id) in T::fields().iter().zip(&self.state_ids) {
+            let state_id = match state_id {
+                Some(state_id) => *state_id,
+                None => {
+                    field_values.push(None);
+                    continue
+                },
+            };
+
+            let value = values.next().ok_or(Error::MissingValue(state_id))?;
+            // the manifest may have changed since binding
+            if value.get_type() != field.data_type {
+                return Err(Error::TypeMismatch(state_id, field.data_type, value.get_type()))
+            }
+            field_values.push(Some(value));
+        }
+
+        T::from_values(field_values).ok_or(Error::InvalidState(std::any::type_name::<T>()))
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use futures::Stream;
+use tokio::sync::{mpsc, Notify};
+use crate::manifest::Manifest;
+use crate::typed_value::TypedValue;
+
+/// Values a subscriber hasn't consumed yet are dropped beyond this many.
+const SUBSCRIPTION_BUFFER: usize = 16;
+/// Shorter intervals are polled at this one, rather than flooding the API with requests.
+const MIN_INTERVAL: Duration = Duration::from_millis(10);
+/// Longer intervals are polled at this one, which keeps every poll time representable.
+const MAX_INTERVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+struct Subscriber {
+    key: u64,
+    interval: Duration,
+    next_delivery: Instant,
+    sender: mpsc::Sender<TypedValue>,
+}
+
+struct PolledState {
+    /// Set when subscribed by path, so the state can be found again in a new manifest.
+    path: Option<String>,
+    poll_interval: Duration,
+    next_poll: Instant,
+    subscribers: Vec<Subscriber>,
+}
+
+impl PolledState {
+    fn update_poll_interval(&mut self) {
+        if let Some(interval) = self.subscribers.iter().map(|subscriber| subscriber.interval).min() {
+            self.poll_interval = interval;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    next_key: u64,
+    states: HashMap<i32, PolledState>,
+}
+
+#[derive(Default)]
+struct Shared {
+    registry: Mutex<Registry>,
+    changed: Notify,
+}
+
+/// The set of states being polled and their subscribers.
+///
+/// Every state is polled once per the shortest interval of its subscribers, no matter how
+/// many there are; each subscriber then only gets the responses that fit its own interval.
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    shared: Arc<Shared>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start polling a state at the given interval, at least 10 ms. States subscribed with their path
+    /// follow it to its new id when a different manifest is received.
+    pub fn subscribe(&self, state_id: i32, path: Option<&str>, interval: Duration) -> Subscription {
+        let interval = interval.clamp(MIN_INTERVAL, MAX_INTERVAL);
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let now = Instant::now();
+
+        let mut registry = self.lock();
+        let key = registry.next_key;
+        registry.next_key += 1;
+
+        let state = registry.states.entry(state_id).or_insert_with(|| PolledState {
+            path: None,
+            poll_interval: interval,
+            next_poll: now,
+            subscribers: Vec::new(),
+        });
+        if state.path.is_none() {
+            state.path = path.map(str::to_string);
+        }
+        state.subscribers.push(Subscriber { key, interval, next_delivery: now, sender });
+        state.update_poll_interval();
+        drop(registry);
+
+        self.shared.changed.notify_one();
+
+        Subscription {
+            key,
+            receiver,
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+
+    /// Ids of the states that have subscribers.
+    pub fn subscribed_ids(&self) -> Vec<i32> {
+        self.lock().states.keys().copied().collect()
+    }
+
+    /// Get the ids of the states due to be polled and schedule their next poll.
+    pub fn due_requests(&self, now: Instant) -> Vec<i32> {
+        let mut due = Vec::new();
+        for (state_id, state) in self.lock().states.iter_mut() {
+            if state.next_poll <= now {
+                state.next_poll += state.poll_interval;
+                // don't try to catch up on polls missed while nobody was polling
+                if state.next_poll <= now {
+                    state.next_poll = now + state.poll_interval;
+                }
+                due.push(*state_id);
+            }
+        }
+
+        due
+    }
+
+    /// When the next state is due to be polled, if any are subscribed.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.lock().states.values().map(|state| state.next_poll).min()
+    }
+
+    /// Wait until a subscription is added or removed.
+    pub async fn changed(&self) {
+        self.shared.changed.notified().await
+    }
+
+    /// Move states subscribed by path to their ids in a newly received manifest.
+    pub fn rebind(&self, manifest: &Manifest) {
+        let mut registry = self.lock();
+        let states: Vec<(i32, PolledState)> = registry.states.drain().collect();
+        for (state_id, state) in states {
+            let new_id = state.path.as_deref()
+                .and_then(|path| manifest.get_entry_by_path(path).ok())
+                .map_or(state_id, |entry| entry.id);
+
+            match registry.states.get_mut(&new_id) {
+                Some(existing) => {
+                    existing.subscribers.extend(state.subscribers);
+                    existing.update_poll_interval();
+                },
+                None => { registry.states.insert(new_id, state); },
+            }
+        }
+    }
+
+    /// Wake up whoever is waiting in [`Subscriptions::changed`].
+    pub fn wake(&self) {
+        self.shared.changed.notify_one();
+    }
+
+    /// Hand a received value to the subscribers of its state that are due for one.
+    pub fn deliver(&self, state_id: i32, value: &TypedValue) {
+        let now = Instant::now();
+        let mut registry = self.lock();
+        let state = match registry.states.get_mut(&state_id) {
+            Some(state) => state,
+            None => return,
+        };
+
+        // responses don't arrive exactly one interval apart, allow some jitter
+        let tolerance = state.poll_interval / 2;
+        state.subscribers.retain_mut(|subscriber| {
+            if now + tolerance < subscriber.next_delivery {
+                return true
+            }
+            subscriber.next_delivery = now + subscriber.interval;
+
+            match subscriber.sender.try_send(value.clone()) {
+                // a slow subscriber misses values rather than piling them up
+                Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => true,
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        lock(&self.shared)
+    }
+}
+
+/// A stream of values of a subscribed state. The subscription ends when this is dropped.
+pub struct Subscription {
+    key: u64,
+    receiver: mpsc::Receiver<TypedValue>,
+    shared: Weak<Shared>,
+}
+
+impl Subscription {
+    /// The id of the polled state, `None` if the connection has been dropped.
+    pub fn state_id(&self) -> Option<i32> {
+        let shared = self.shared.upgrade()?;
+        let registry = lock(&shared);
+        Self::find_state(&registry, self.key)
+    }
+
+    fn find_state(registry: &Registry, key: u64) -> Option<i32> {
+        registry.states.iter()
+            .find(|(_, state)| state.subscribers.iter().any(|subscriber| subscriber.key == key))
+            .map(|(state_id, _)| *state_id)
+    }
+
+    /// Wait for the next value. Returns `None` once the connection has been dropped.
+    pub async fn recv(&mut self) -> Option<TypedValue> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for Subscription {
+    type Item = TypedValue;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let shared = match self.shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+
+        // the state may have moved to another id since subscribing
+        let mut registry = lock(&shared);
+        if let Some(state_id) = Self::find_state(&registry, self.key) {
+            if let Some(state) = registry.states.get_mut(&state_id) {
+                state.subscribers.retain(|subscriber| subscriber.key != self.key);
+                if state.subscribers.is_empty() {
+                    registry.states.remove(&state_id);
+                } else {
+                    state.update_poll_interval();
+                }
+            }
+        }
+        drop(registry);
+
+        shared.changed.notify_one();
+    }
+}
+
+fn lock(shared: &Shared) -> MutexGuard<'_, Registry> {
+    shared.registry.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,281 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+#[cfg(feature = "discovery")]
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use crate::codec::{encode_response, MANIFEST_ID};
+#[cfg(feature = "discovery")]
+use crate::discovery::InstanceInformation;
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::transport::{duplex, DuplexStream, Transport};
+use crate::typed_value::{Type, TypedValue};
+
+const MOCK_ADDRESS: &str = "127.0.0.1";
+#[cfg(feature = "discovery")]
+const MOCK_DEVICE_ID: &str = "mock-device";
+const IN_MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A request received by the [`MockServer`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordedRequest {
+    Get(i32),
+    Set(i32, TypedValue),
+    Run(i32),
+}
+
+#[derive(Default)]
+struct MockState {
+    /// Values answered to get requests. States with more than one value answer them
+    /// in order and then keep answering the last one.
+    values: HashMap<i32, VecDeque<TypedValue>>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// An in-process stand-in for Infinite Flight, serving the Connect v2 protocol on a local port.
+///
+/// The server answers the manifest request with its manifest and get requests from its state
+/// table. Set requests update the state table; set and run requests are recorded so tests can
+/// assert on them. Every client connected to the server shares the same state, including the
+/// ones connected in memory with [`MockServer::connect_in_memory`].
+pub struct MockServer {
+    address: SocketAddr,
+    manifest: Manifest,
+    state: Arc<Mutex<MockState>>,
+    disconnect: watch::Sender<u64>,
+    in_memory: mpsc::UnboundedSender<DuplexStream>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Start listening on a free port on loopback.
+    pub async fn start(manifest: Manifest) -> Result<Self, Error> {
+        let listener = TcpListener::bind((MOCK_ADDRESS, 0)).await?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let (disconnect, _) = watch::channel(0);
+        let (in_memory, in_memory_receiver) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(Self::accept(listener, in_memory_receiver, manifest.clone(), state.clone(), disconnect.subscribe()));
+
+        Ok(Self { address, manifest, state, disconnect, in_memory, task })
+    }
+
+    /// Connect to the server without a socket. The returned stream can be used with
+    /// [`Connection::spawn_transport`](crate::connection::Connection::spawn_transport).
+    pub fn connect_in_memory(&self) -> DuplexStream {
+        let (client, server) = duplex(IN_MEMORY_BUFFER_SIZE);
+        // the receiver lives as long as the server
+        let _ = self.in_memory.send(server);
+        client
+    }
+
+    /// The address clients should connect to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Answer get requests for the state at the given path with this value.
+    pub fn set_state(&self, path: &str, value: TypedValue) -> Result<(), Error> {
+        let id = self.manifest.get_entry_by_path(path)?.id;
+        self.set_state_id(id, value);
+        Ok(())
+    }
+
+    pub fn set_state_id(&self, state_id: i32, value: TypedValue) {
+        self.lock().values.insert(state_id, VecDeque::from([value]));
+    }
+
+    /// Answer consecutive get requests for the state at the given path with these values,
+    /// repeating the last one once the script has run out.
+    pub fn script_state(&self, path: &str, values: Vec<TypedValue>) -> Result<(), Error> {
+        let id = self.manifest.get_entry_by_path(path)?.id;
+        self.script_state_id(id, values);
+        Ok(())
+    }
+
+    pub fn script_state_id(&self, state_id: i32, values: Vec<TypedValue>) {
+        self.lock().values.insert(state_id, values.into());
+    }
+
+    /// The value the next get request for this state would be answered with.
+    pub fn get_state_id(&self, state_id: i32) -> Option<TypedValue> {
+        self.lock().values.get(&state_id).and_then(|values| values.front().cloned())
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Take the requests received so far, so the next call only returns new ones.
+    pub fn take_requests(&self) -> Vec<RecordedRequest> {
+        std::mem::take(&mut self.lock().requests)
+    }
+
+    /// The set requests received so far.
+    pub fn set_requests(&self) -> Vec<(i32, TypedValue)> {
+        self.lock().requests.iter()
+            .filter_map(|request| match request {
+                RecordedRequest::Set(id, value) => Some((*id, value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The ids of the commands run so far.
+    pub fn run_requests(&self) -> Vec<i32> {
+        self.lock().requests.iter()
+            .filter_map(|request| match request {
+                RecordedRequest::Run(id) => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Close the connections of all clients connected right now. The server keeps listening.
+    pub fn disconnect_clients(&self) {
+        self.disconnect.send_modify(|generation| *generation += 1);
+    }
+
+    /// The instance information this server broadcasts.
+    #[cfg(feature = "discovery")]
+    pub fn instance_information(&self) -> InstanceInformation {
+        InstanceInformation {
+            state: "Playing".to_string(),
+            port: self.address.port() as u32,
+            device_id: MOCK_DEVICE_ID.to_string(),
+            aircraft: "Mock Aircraft".to_string(),
+            version: "0.0.0".to_string(),
+            device_name: "Mock Server".to_string(),
+            addresses: vec![MOCK_ADDRESS.to_string()],
+            livery: "Mock Livery".to_string(),
+        }
+    }
+
+    /// Send a single discovery broadcast for this server to the given UDP port on loopback.
+    #[cfg(feature = "discovery")]
+    pub async fn broadcast_instance(&self, udp_port: u16) -> Result<(), Error> {
+        let message = serde_json::to_vec(&self.instance_information()).expect("instance information is always serializable");
+
+        let socket = UdpSocket::bind((MOCK_ADDRESS, 0)).await?;
+        socket.send_to(&message, (MOCK_ADDRESS, udp_port)).await?;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        lock(&self.state)
+    }
+
+    async fn accept(listener: TcpListener, mut in_memory: mpsc::UnboundedReceiver<DuplexStream>, manifest: Manifest, state: Arc<Mutex<MockState>>, disconnect: watch::Receiver<u64>) {
+        // dropping the set when the server is stopped stops the clients too
+        let mut clients = JoinSet::new();
+        loop {
+            let transport: Box<dyn Transport> = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => Box::new(stream),
+                    Err(_) => break,
+                },
+                Some(stream) = in_memory.recv() => Box::new(stream),
+            };
+
+            let client = MockClient { manifest: manifest.clone(), state: state.clone() };
+            let mut disconnect = disconnect.clone();
+            disconnect.mark_unchanged();
+
+            clients.spawn(async move {
+                tokio::select! {
+                    _ = client.serve(transport) => {},
+                    _ = disconnect.changed() => {},
+                }
+            });
+            // forget the clients that have finished
+            while clients.try_join_next().is_some() {}
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+struct MockClient {
+    manifest: Manifest,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockClient {
+    /// Serve requests until the client disconnects or sends something that can't be parsed.
+    async fn serve<T: Transport>(self, mut stream: T) -> Result<(), Error> {
+        loop {
+            let id = stream.read_i32_le().await?;
+            let is_set = stream.read_i32_le().await? != 0;
+
+            let request = if is_set {
+                let data_type = self.manifest.get_data_type_for_id(&id)?;
+                RecordedRequest::Set(id, Self::read_value(&mut stream, &data_type).await?)
+            } else if self.manifest.get_entry_by_id(&id).is_ok_and(|entry| entry.is_command()) {
+                RecordedRequest::Run(id)
+            } else {
+                RecordedRequest::Get(id)
+            };
+
+            let response = self.handle(request);
+            if let Some(response) = response {
+                stream.write_all(&response).await?;
+            }
+        }
+    }
+
+    fn handle(&self, request: RecordedRequest) -> Option<Vec<u8>> {
+        let mut state = lock(&self.state);
+        state.requests.push(request.clone());
+
+        match request {
+            RecordedRequest::Get(MANIFEST_ID) => Some(encode_response(MANIFEST_ID, &TypedValue::String(self.manifest.to_string()))),
+            RecordedRequest::Get(id) => {
+                // states without a value are never answered, like unknown ids in the real API
+                let values = state.values.get_mut(&id)?;
+                let value = if values.len() > 1 { values.pop_front()? } else { values.front()?.clone() };
+                Some(encode_response(id, &value))
+            },
+            RecordedRequest::Set(id, value) => {
+                state.values.insert(id, VecDeque::from([value]));
+                None
+            },
+            RecordedRequest::Run(_) => None,
+        }
+    }
+
+    async fn read_value<T: Transport>(stream: &mut T, data_type: &Type) -> Result<TypedValue, Error> {
+        let value = match data_type {
+            Type::Boolean => TypedValue::Boolean(stream.read_i32_le().await? != 0),
+            Type::Integer32 => TypedValue::Integer32(stream.read_i32_le().await?),
+            Type::Float => TypedValue::Float(stream.read_f32_le().await?),
+            Type::Double => TypedValue::Double(stream.read_f64_le().await?),
+            Type::Long => TypedValue::Long(stream.read_i64_le().await?),
+            Type::String => {
+                let length = stream.read_i32_le().await?.max(0) as usize;
+                let mut bytes = vec![0u8; length];
+                stream.read_exact(&mut bytes).await?;
+                TypedValue::String(String::from_utf8_lossy(&bytes).into_owned())
+            },
+        };
+
+        Ok(value)
+    }
+}
+
+fn lock(state: &Mutex<MockState>) -> MutexGuard<'_, MockState> {
+    state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,155 @@
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+#[cfg(feature = "discovery")]
+use std::time::Duration;
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(feature = "discovery")]
+use crate::discovery::{discover_instances, DiscoveryOptions};
+#[cfg(feature = "discovery")]
+use crate::helpers::get_ipv4_addresses;
+#[cfg(feature = "discovery")]
+use crate::TCP_PORT_V2;
+
+pub use tokio::io::{duplex, DuplexStream};
+
+/// A byte stream the protocol can run over.
+///
+/// Implemented for every `AsyncRead + AsyncWrite` stream, including [`TcpStream`],
+/// [`DuplexStream`] for in-memory connections and `UnixStream` on Unix. TLS tunnels or SSH
+/// port-forwards can be used by wrapping them in such a stream.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Transport for T {}
+
+/// Opens a new transport when a spawned connection reconnects, see
+/// [`Connection::spawn_transport_with`](crate::connection::Connection::spawn_transport_with).
+///
+/// Implemented for closures returning a future of a transport.
+pub trait Connector: Send + 'static {
+    type Transport: Transport;
+
+    fn connect(&mut self) -> BoxFuture<'_, io::Result<Self::Transport>>;
+}
+
+impl<F, Fut, T> Connector for F
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = io::Result<T>> + Send + 'static,
+    T: Transport,
+{
+    type Transport = T;
+
+    fn connect(&mut self) -> BoxFuture<'_, io::Result<T>> {
+        Box::pin(self())
+    }
+}
+
+/// Connects over TCP to the first of a list of addresses that accepts the connection.
+#[derive(Debug, Clone)]
+pub struct TcpConnector {
+    addresses: Vec<SocketAddr>,
+    /// Device id of the instance and how long to listen for it before connecting.
+    #[cfg(feature = "discovery")]
+    rediscover: Option<(String, Duration)>,
+}
+
+impl TcpConnector {
+    pub fn new(addresses: Vec<SocketAddr>) -> Self {
+        Self {
+            addresses,
+            #[cfg(feature = "discovery")]
+            rediscover: None,
+        }
+    }
+
+    /// Look for the instance on the LAN again before connecting, in case its address changed.
+    #[cfg(feature = "discovery")]
+    pub(crate) fn rediscovering(mut self, device_id: String, window: Duration) -> Self {
+        self.rediscover = Some((device_id, window));
+        self
+    }
+
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Update the addresses of the instance if it is still broadcasting on the LAN.
+    #[cfg(feature = "discovery")]
+    async fn rediscover(&mut self) {
+        let (device_id, window) = match &self.rediscover {
+            Some(rediscover) => rediscover,
+            None => return,
+        };
+
+        let options = DiscoveryOptions { window: Some(*window), ..DiscoveryOptions::default() };
+        let instances = match discover_instances(options).await {
+            Ok(instances) => instances,
+            Err(_) => return,
+        };
+
+        if let Some(instance) = instances.into_iter().find(|instance| &instance.device_id == device_id) {
+            let addresses: Vec<SocketAddr> = get_ipv4_addresses(instance.addresses).iter()
+                .filter_map(|ip| format!("{}:{}", ip, TCP_PORT_V2).parse().ok())
+                .collect();
+            if !addresses.is_empty() {
+                self.addresses = addresses;
+            }
+        }
+    }
+}
+
+impl Connector for TcpConnector {
+    type Transport = TcpStream;
+
+    fn connect(&mut self) -> BoxFuture<'_, io::Result<TcpStream>> {
+        Box::pin(async move {
+            #[cfg(feature = "discovery")]
+            self.rediscover().await;
+            TcpStream::connect(&self.addresses[..]).await
+        })
+    }
+}
+
+/// Connects to a Unix domain socket, e.g. one forwarded to the API over SSH.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    pub fn new<P: Into<std::path::PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    type Transport = tokio::net::UnixStream;
+
+    fn connect(&mut self) -> BoxFuture<'_, io::Result<tokio::net::UnixStream>> {
+        Box::pin(tokio::net::UnixStream::connect(&self.path))
+    }
+}
+
+/// For transports that can't be opened again, like one half of a [`duplex`].
+pub(crate) struct NoReconnect<T>(PhantomData<fn() -> T>);
+
+impl<T> NoReconnect<T> {
+    pub(crate) fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: Transport> Connector for NoReconnect<T> {
+    type Transport = T;
+
+    fn connect(&mut self) -> BoxFuture<'_, io::Result<T>> {
+        Box::pin(async { Err(io::ErrorKind::Unsupported.into()) })
+    }
+}
@@ -1,6 +1,13 @@
+use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::error::ValueError;
 
-#[derive(Clone)]
+/// A value of a state. With the `serde` feature it is serialized with its type, e.g. `{"Double":1.5}`;
+/// see [`untagged`] for the plain value.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TypedValue {
     Boolean(bool),
     Integer32(i32),
@@ -10,7 +17,8 @@ pub enum TypedValue {
     Long(i64)
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Type {
     Boolean,
     Integer32,
@@ -20,19 +28,188 @@ pub enum Type {
     Long
 }
 
+impl Type {
+    /// Get the type for a data type id used in the manifest.
+    pub fn from_id(data_type: i32) -> Option<Self> {
+        match data_type {
+            0 => Some(Type::Boolean),
+            1 => Some(Type::Integer32),
+            2 => Some(Type::Float),
+            3 => Some(Type::Double),
+            4 => Some(Type::String),
+            5 => Some(Type::Long),
+            _ => None,
+        }
+    }
+
+    pub fn of(value: &TypedValue) -> Self {
+        match value {
+            TypedValue::Boolean(_) => Type::Boolean,
+            TypedValue::Integer32(_) => Type::Integer32,
+            TypedValue::Float(_) => Type::Float,
+            TypedValue::Double(_) => Type::Double,
+            TypedValue::String(_) => Type::String,
+            TypedValue::Long(_) => Type::Long,
+        }
+    }
+}
+
 impl TypedValue {
+    pub fn get_type(&self) -> Type {
+        Type::of(self)
+    }
+
+    /// Parse a value of the given type, e.g. from command line input or a config file.
+    ///
+    /// Booleans are `true`/`false` (in any case) or `1`/`0`. Whitespace around numbers and
+    /// booleans is ignored; strings are taken as they are.
+    pub fn parse(input: &str, data_type: &Type) -> Result<Self, ValueError> {
+        let trimmed = input.trim();
+        let invalid = || ValueError::Invalid(*data_type, input.to_string());
+
+        let value = match data_type {
+            Type::Boolean => match trimmed.to_ascii_lowercase().as_str() {
+                "true" | "1" => Self::Boolean(true),
+                "false" | "0" => Self::Boolean(false),
+                _ => return Err(invalid()),
+            },
+            Type::Integer32 => Self::Integer32(trimmed.parse().map_err(|_| invalid())?),
+            Type::Float => Self::Float(trimmed.parse().map_err(|_| invalid())?),
+            Type::Double => Self::Double(trimmed.parse().map_err(|_| invalid())?),
+            Type::String => Self::String(input.to_string()),
+            Type::Long => Self::Long(trimmed.parse().map_err(|_| invalid())?),
+        };
+
+        Ok(value)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// The value of an `Integer32` or `Long`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Integer32(val) => Some(*val as i64),
+            Self::Long(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// The value of any number. `Long` values beyond 2^53 lose precision.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Integer32(val) => Some(*val as f64),
+            Self::Float(val) => Some(*val as f64),
+            Self::Double(val) => Some(*val),
+            Self::Long(val) => Some(*val as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Convert the value to the given type if that can be done without losing information.
+    pub fn coerce_to(&self, data_type: &Type) -> Option<TypedValue> {
+        match (self, data_type) {
+            (value, data_type) if value.get_type() == *data_type => Some(value.clone()),
+            (Self::Boolean(val), Type::Integer32) => Some(Self::Integer32(*val as i32)),
+            (Self::Integer32(val), Type::Long) => Some(Self::Long(*val as i64)),
+            (Self::Integer32(val), Type::Double) => Some(Self::Double(*val as f64)),
+            (Self::Float(val), Type::Double) => Some(Self::Double(*val as f64)),
+            _ => None,
+        }
+    }
+
+    /// Encode the value the way the API expects it in a set request: little-endian numbers,
+    /// booleans as an `i32` and an `i32` length followed by UTF-8 bytes for strings.
     pub fn to_bytes_vec(&self) -> Vec<u8> {
-        let vec_arr;
         match self {
-            Self::Boolean(val) => vec_arr = Vec::from((*val as i32).to_le_bytes()),
-            Self::Integer32(val) => vec_arr = Vec::from(val.to_le_bytes()),
-            Self::Float(val) => vec_arr = Vec::from(val.to_le_bytes()),
-            Self::Double(val) => vec_arr = Vec::from(val.to_le_bytes()),
-            Self::String(val) => vec_arr = val.to_owned().into_bytes(),
-            Self::Long(val) => vec_arr = Vec::from(val.to_le_bytes()),
+            Self::Boolean(val) => Vec::from((*val as i32).to_le_bytes()),
+            Self::Integer32(val) => Vec::from(val.to_le_bytes()),
+            Self::Float(val) => Vec::from(val.to_le_bytes()),
+            Self::Double(val) => Vec::from(val.to_le_bytes()),
+            Self::String(val) => {
+                let mut bytes = Vec::from((val.len() as i32).to_le_bytes());
+                bytes.extend(val.as_bytes());
+                bytes
+            },
+            Self::Long(val) => Vec::from(val.to_le_bytes()),
+        }
+    }
+}
+
+/// Values of the same type compare by value, values of different types are unordered.
+impl PartialOrd for TypedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Boolean(a), Self::Boolean(b)) => a.partial_cmp(b),
+            (Self::Integer32(a), Self::Integer32(b)) => a.partial_cmp(b),
+            (Self::Float(a), Self::Float(b)) => a.partial_cmp(b),
+            (Self::Double(a), Self::Double(b)) => a.partial_cmp(b),
+            (Self::String(a), Self::String(b)) => a.partial_cmp(b),
+            (Self::Long(a), Self::Long(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_conversions {
+    ($ty:ty, $variant:ident) => {
+        impl From<$ty> for TypedValue {
+            fn from(val: $ty) -> Self {
+                Self::$variant(val)
+            }
+        }
+
+        /// Only values of the same type convert.
+        impl TryFrom<TypedValue> for $ty {
+            type Error = ValueError;
+
+            fn try_from(value: TypedValue) -> Result<Self, ValueError> {
+                match value {
+                    TypedValue::$variant(val) => Ok(val),
+                    value => Err(ValueError::WrongType(Type::$variant, value.get_type())),
+                }
+            }
+        }
+    };
+    ($ty:ty, $variant:ident, $accessor:ident) => {
+        impl From<$ty> for TypedValue {
+            fn from(val: $ty) -> Self {
+                Self::$variant(val)
+            }
         }
 
-        vec_arr
+        /// Converts the values the matching `as_*` accessor reads.
+        impl TryFrom<TypedValue> for $ty {
+            type Error = ValueError;
+
+            fn try_from(value: TypedValue) -> Result<Self, ValueError> {
+                value.$accessor().ok_or_else(|| ValueError::WrongType(Type::$variant, value.get_type()))
+            }
+        }
+    };
+}
+
+impl_conversions!(bool, Boolean, as_bool);
+impl_conversions!(i32, Integer32);
+impl_conversions!(f32, Float);
+impl_conversions!(f64, Double, as_f64);
+impl_conversions!(String, String);
+impl_conversions!(i64, Long, as_i64);
+
+impl From<&str> for TypedValue {
+    fn from(val: &str) -> Self {
+        Self::String(val.to_string())
     }
 }
 
@@ -48,3 +225,72 @@ impl Display for TypedValue {
         })
     }
 }
+
+/// Serialize a [`TypedValue`] as the plain value it holds, e.g. `1.5` instead of `{"Double":1.5}`.
+/// Use with `#[serde(with = "ifconnect::typed_value::untagged")]`, or wrap values in [`Untagged`].
+///
+/// The type doesn't survive the round trip: whole numbers are deserialized as `Integer32` if they
+/// fit and as `Long` otherwise, other numbers as `Double`.
+#[cfg(feature = "serde")]
+pub mod untagged {
+    use std::fmt::Formatter;
+    use serde::de::{Error, Visitor};
+    use serde::{Deserializer, Serializer};
+    use super::TypedValue;
+
+    pub fn serialize<S: Serializer>(value: &TypedValue, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            TypedValue::Boolean(val) => serializer.serialize_bool(*val),
+            TypedValue::Integer32(val) => serializer.serialize_i32(*val),
+            TypedValue::Float(val) => serializer.serialize_f32(*val),
+            TypedValue::Double(val) => serializer.serialize_f64(*val),
+            TypedValue::String(val) => serializer.serialize_str(val),
+            TypedValue::Long(val) => serializer.serialize_i64(*val),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TypedValue, D::Error> {
+        deserializer.deserialize_any(UntaggedVisitor)
+    }
+
+    struct UntaggedVisitor;
+
+    impl<'de> Visitor<'de> for UntaggedVisitor {
+        type Value = TypedValue;
+
+        fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.write_str("a boolean, number or string")
+        }
+
+        fn visit_bool<E: Error>(self, val: bool) -> Result<TypedValue, E> {
+            Ok(TypedValue::Boolean(val))
+        }
+
+        fn visit_i64<E: Error>(self, val: i64) -> Result<TypedValue, E> {
+            Ok(i32::try_from(val).map(TypedValue::Integer32).unwrap_or(TypedValue::Long(val)))
+        }
+
+        fn visit_u64<E: Error>(self, val: u64) -> Result<TypedValue, E> {
+            let val = i64::try_from(val).map_err(|_| E::custom(format!("{} doesn't fit in a Long", val)))?;
+            self.visit_i64(val)
+        }
+
+        fn visit_f64<E: Error>(self, val: f64) -> Result<TypedValue, E> {
+            Ok(TypedValue::Double(val))
+        }
+
+        fn visit_str<E: Error>(self, val: &str) -> Result<TypedValue, E> {
+            Ok(TypedValue::String(val.to_string()))
+        }
+
+        fn visit_string<E: Error>(self, val: String) -> Result<TypedValue, E> {
+            Ok(TypedValue::String(val))
+        }
+    }
+}
+
+/// A [`TypedValue`] serialized as its plain value, for collections of values; see [`untagged`].
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Untagged(#[serde(with = "untagged")] pub TypedValue);
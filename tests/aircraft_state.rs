@@ -0,0 +1,74 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::time::Duration;
+use ifconnect::aircraft_state::AircraftState;
+use ifconnect::typed_value::TypedValue;
+use common::{connect, TIMEOUT};
+
+// no true heading, gear or name on this aircraft
+const MANIFEST: &str = "\
+10,3,aircraft/0/latitude
+11,3,aircraft/0/longitude
+12,2,aircraft/0/altitude_msl
+13,2,aircraft/0/heading_magnetic
+14,2,aircraft/0/indicated_airspeed
+15,2,aircraft/0/vertical_speed
+16,0,aircraft/0/is_on_ground
+17,1,aircraft/0/systems/flaps/state
+";
+
+#[tokio::test]
+async fn polls_converted_snapshot() {
+    let (server, handle) = connect(MANIFEST).await;
+    let states = [
+        ("aircraft/0/latitude", TypedValue::Double(47.45)),
+        ("aircraft/0/longitude", TypedValue::Double(-122.31)),
+        ("aircraft/0/altitude_msl", TypedValue::Float(3500.0)),
+        ("aircraft/0/heading_magnetic", TypedValue::Float(std::f32::consts::FRAC_PI_2)),
+        ("aircraft/0/indicated_airspeed", TypedValue::Float(100.0)),
+        ("aircraft/0/vertical_speed", TypedValue::Float(-5.08)),
+        ("aircraft/0/is_on_ground", TypedValue::Boolean(false)),
+        ("aircraft/0/systems/flaps/state", TypedValue::Integer32(2)),
+    ];
+    for (path, value) in states {
+        server.set_state(path, value).unwrap();
+    }
+
+    let state = handle.poll_aircraft_state(TIMEOUT).await.unwrap();
+
+    assert_eq!(state.latitude, Some(47.45));
+    assert_eq!(state.longitude, Some(-122.31));
+    assert_eq!(state.altitude_msl, Some(3500.0));
+    assert!((state.heading_magnetic.unwrap() - 90.0).abs() < 1e-4);
+    assert!((state.indicated_airspeed.unwrap() - 194.38).abs() < 0.01);
+    assert!((state.vertical_speed.unwrap() + 1000.0).abs() < 0.1);
+    assert_eq!(state.on_ground, Some(false));
+    assert_eq!(state.flaps, Some(2));
+
+    assert_eq!(state.heading_true, None);
+    assert_eq!(state.gear, None);
+    assert_eq!(state.name, None);
+    // every state in the manifest was requested, and nothing else
+    assert_eq!(server.requests().len(), 1 + 8);
+}
+
+#[tokio::test]
+async fn waits_without_limit_for_the_longest_timeout() {
+    let (server, handle) = connect(MANIFEST).await;
+    server.set_state("aircraft/0/latitude", TypedValue::Double(47.45)).unwrap();
+    server.set_state("aircraft/0/systems/flaps/state", TypedValue::Integer32(1)).unwrap();
+
+    let values = handle.get_values_id(&[10, 17], Duration::MAX).await.unwrap();
+    assert_eq!(values, vec![TypedValue::Double(47.45), TypedValue::Integer32(1)]);
+}
+
+#[test]
+fn ignores_values_of_unexpected_type() {
+    let mut state = AircraftState::default();
+    state.apply("aircraft/0/is_on_ground", &TypedValue::Integer32(1));
+    state.apply("aircraft/0/unrelated", &TypedValue::Double(1.0));
+
+    assert_eq!(state, AircraftState::default());
+}
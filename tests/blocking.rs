@@ -0,0 +1,93 @@
+#![cfg(all(feature = "blocking", feature = "async"))]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::time::Duration;
+use ifconnect::blocking::Connection;
+use ifconnect::codec::{encode_get_request, encode_response, MANIFEST_ID};
+use ifconnect::error::Error;
+use ifconnect::testing::MockServer;
+use ifconnect::typed_value::TypedValue;
+use common::{start_server, TIMEOUT};
+
+const MANIFEST: &str = "1,4,aircraft/0/name\n2,1,aircraft/0/systems/flaps/state\n3,-1,commands/FlapsDown\n";
+
+async fn start() -> MockServer {
+    let server = start_server(MANIFEST).await;
+    server.set_state("aircraft/0/name", TypedValue::String("E175".to_string())).unwrap();
+    server
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gets_sets_and_runs() {
+    let server = start().await;
+    let address = server.local_addr();
+
+    let (name, flaps) = tokio::task::spawn_blocking(move || {
+        let mut connection = Connection::connect(address).unwrap();
+        let manifest = connection.fetch_manifest(TIMEOUT).unwrap();
+        assert_eq!(manifest.get_number_of_entries(), 3);
+
+        connection.set("aircraft/0/systems/flaps/state", TypedValue::Integer32(1)).unwrap();
+        connection.run("commands/FlapsDown").unwrap();
+        let name = connection.get_value("aircraft/0/name", TIMEOUT).unwrap();
+        let flaps = connection.get_value("aircraft/0/systems/flaps/state", TIMEOUT).unwrap();
+        (name, flaps)
+    }).await.unwrap();
+
+    assert!(matches!(name, TypedValue::String(name) if name == "E175"));
+    assert!(matches!(flaps, TypedValue::Integer32(1)));
+    assert_eq!(server.run_requests(), vec![3]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_out_and_skips_late_responses() {
+    let server = start().await;
+    let address = server.local_addr();
+
+    tokio::task::spawn_blocking(move || {
+        let mut connection = Connection::connect(address).unwrap();
+        assert!(matches!(connection.get_value("aircraft/0/name", TIMEOUT), Err(Error::NoManifest())));
+        connection.fetch_manifest(TIMEOUT).unwrap();
+
+        // the mock server never answers states without a value
+        let result = connection.get_value("aircraft/0/systems/flaps/state", Duration::from_millis(50));
+        assert!(matches!(result, Err(Error::Timeout(2))));
+
+        let result = connection.set("aircraft/0/name", TypedValue::Integer32(1));
+        assert!(matches!(result, Err(Error::TypeMismatch(1, _, _))));
+        let name = connection.get_value("aircraft/0/name", TIMEOUT).unwrap();
+        assert!(matches!(name, TypedValue::String(name) if name == "E175"));
+    }).await.unwrap();
+}
+
+#[test]
+fn skips_late_response_to_timed_out_request() {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+
+    // an API answering the first request for the flaps after the client gave up on it
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = vec![0u8; encode_get_request(MANIFEST_ID).len()];
+        stream.read_exact(&mut request).unwrap();
+        stream.write_all(&encode_response(MANIFEST_ID, &TypedValue::String(MANIFEST.to_string()))).unwrap();
+
+        for flaps in [1, 2] {
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, encode_get_request(2));
+            if flaps == 1 {
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            stream.write_all(&encode_response(2, &TypedValue::Integer32(flaps))).unwrap();
+        }
+    });
+
+    let mut connection = Connection::connect(address).unwrap();
+    connection.fetch_manifest(Duration::MAX).unwrap();
+    let result = connection.get_value_id(2, Duration::from_millis(50));
+    assert!(matches!(result, Err(Error::Timeout(2))));
+    assert_eq!(connection.get_value_id(2, TIMEOUT).unwrap(), TypedValue::Integer32(2));
+    server.join().unwrap();
+}
@@ -0,0 +1,143 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ifconnect::capture::{Capture, CaptureRecord, Direction, Recorder, ReplayOptions};
+use ifconnect::codec::{encode_get_request, encode_response, MANIFEST_ID};
+use ifconnect::connection::Connection;
+use ifconnect::error::{CaptureError, Error};
+use ifconnect::manifest::Manifest;
+use ifconnect::typed_value::TypedValue;
+use common::{start_server, TIMEOUT};
+
+const MANIFEST: &str = "1,3,aircraft/0/heading_magnetic\n2,1,aircraft/0/flaps\n";
+
+#[tokio::test]
+async fn replays_recorded_session() {
+    let server = start_server(MANIFEST).await;
+    server.script_state("aircraft/0/heading_magnetic", vec![TypedValue::Double(90.0), TypedValue::Double(91.5)]).unwrap();
+    server.set_state("aircraft/0/flaps", TypedValue::Integer32(2)).unwrap();
+
+    let path = std::env::temp_dir().join(format!("ifconnect-capture-{}.ifcc", std::process::id()));
+    let handle = Connection::spawn(server.local_addr()).await.unwrap();
+    handle.start_recording(Recorder::create(&path).unwrap());
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+    handle.get_value("aircraft/0/heading_magnetic", TIMEOUT).await.unwrap();
+    handle.get_value("aircraft/0/flaps", TIMEOUT).await.unwrap();
+    handle.get_value("aircraft/0/heading_magnetic", TIMEOUT).await.unwrap();
+    handle.stop_recording().unwrap();
+
+    let capture = Capture::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(capture.records().iter().filter(|record| record.direction == Direction::Sent).count(), 4);
+    assert!(capture.records().windows(2).all(|records| records[0].timestamp <= records[1].timestamp));
+
+    let replay = Connection::replay(capture, ReplayOptions { speed: f64::INFINITY });
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let manifests = Arc::new(Mutex::new(0));
+    {
+        let received = received.clone();
+        replay.handle().on_receive_data(Some(move |args: ifconnect::event_args::ReceivedDataArgs| {
+            received.lock().unwrap().push(format!("{}={}", args.command_id, args.data));
+        }));
+        let manifests = manifests.clone();
+        replay.handle().on_receive_manifest(Some(move |_| *manifests.lock().unwrap() += 1));
+    }
+    let handle = replay.handle().clone();
+    replay.run().await.unwrap();
+
+    assert_eq!(*manifests.lock().unwrap(), 1);
+    assert_eq!(*received.lock().unwrap(), vec!["1=90", "2=2", "1=91.5"]);
+    assert_eq!(handle.manifest().unwrap(), *server.manifest());
+}
+
+/// Replay a manifest received an hour into the session, which must not be waited for.
+async fn replay_hour_long_capture(speed: f64) {
+    let record = |direction, bytes| CaptureRecord { timestamp: Duration::from_secs(3600), direction, bytes };
+    let records = vec![
+        record(Direction::Sent, encode_get_request(MANIFEST_ID)),
+        record(Direction::Received, encode_response(MANIFEST_ID, &TypedValue::String(MANIFEST.to_string()))),
+    ];
+
+    let replay = Connection::replay(Capture::from_records(records), ReplayOptions { speed });
+    let handle = replay.handle().clone();
+    tokio::time::timeout(TIMEOUT, replay.run()).await.unwrap().unwrap();
+    assert_eq!(handle.manifest().unwrap(), Manifest::parse(MANIFEST).unwrap());
+}
+
+#[tokio::test]
+async fn replays_zero_speed_without_waiting() {
+    replay_hour_long_capture(0.0).await;
+}
+
+#[tokio::test]
+async fn replays_negative_speed_without_waiting() {
+    replay_hour_long_capture(-2.0).await;
+}
+
+#[tokio::test]
+async fn replays_nan_speed_without_waiting() {
+    replay_hour_long_capture(f64::NAN).await;
+}
+
+#[tokio::test]
+async fn replays_infinite_speed_without_waiting() {
+    replay_hour_long_capture(f64::INFINITY).await;
+}
+
+#[test]
+fn reads_back_written_records() {
+    let buffer = SharedBuffer::default();
+    let mut recorder = Recorder::new(buffer.clone()).unwrap();
+    recorder.record(Direction::Sent, &[1, 0, 0, 0, 0]).unwrap();
+    recorder.record(Direction::Received, &[]).unwrap();
+    drop(recorder);
+
+    let bytes = buffer.0.lock().unwrap().clone();
+    let capture = Capture::read_from(&bytes[..]).unwrap();
+    let directions: Vec<Direction> = capture.records().iter().map(|record| record.direction).collect();
+    assert_eq!(directions, vec![Direction::Sent, Direction::Received]);
+    assert_eq!(capture.records()[0].bytes, vec![1, 0, 0, 0, 0]);
+
+    let truncated = Capture::read_from(&bytes[..bytes.len() - 3]);
+    assert!(matches!(truncated, Err(Error::Capture(CaptureError::Truncated(1)))));
+    assert!(matches!(Capture::read_from(&b"nope!"[..]), Err(Error::Capture(CaptureError::InvalidHeader))));
+}
+
+#[test]
+fn rejects_corrupted_record_length() {
+    let buffer = SharedBuffer::default();
+    let mut recorder = Recorder::new(buffer.clone()).unwrap();
+    recorder.record(Direction::Received, &[1, 2, 3]).unwrap();
+    drop(recorder);
+
+    // claim the record is 4 GiB long
+    let mut bytes = buffer.0.lock().unwrap().clone();
+    let length_offset = bytes.len() - 3 - 4;
+    bytes[length_offset..length_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(Capture::read_from(&bytes[..]), Err(Error::Capture(CaptureError::Truncated(0)))));
+}
+
+#[test]
+fn capture_duration_is_last_timestamp() {
+    let record = |millis| CaptureRecord { timestamp: Duration::from_millis(millis), direction: Direction::Received, bytes: Vec::new() };
+    let capture = Capture::from_records(vec![record(5), record(40)]);
+
+    assert_eq!(capture.duration(), Duration::from_millis(40));
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use ifconnect::codec::{encode_get_request, encode_set_request, Frame, FrameDecoder};
+use ifconnect::error::FrameError;
+use ifconnect::Error;
+use ifconnect::typed_value::{Type, TypedValue};
+
+fn response_bytes(id: i32, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend(id.to_le_bytes());
+    bytes.extend((payload.len() as i32).to_le_bytes());
+    bytes.extend(payload);
+    bytes
+}
+
+#[test]
+fn decodes_frame_split_across_reads() {
+    let bytes = response_bytes(42, &7i32.to_le_bytes());
+    let mut decoder = FrameDecoder::new();
+
+    for chunk in bytes.chunks(2) {
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.extend(chunk);
+    }
+
+    let frame = decoder.next_frame().unwrap().unwrap();
+    assert_eq!(frame, Frame { id: 42, payload: 7i32.to_le_bytes().to_vec() });
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn decodes_coalesced_frames() {
+    let mut bytes = response_bytes(1, &[1]);
+    bytes.extend(response_bytes(2, &2.5f64.to_le_bytes()));
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&bytes);
+
+    let first = decoder.next_frame().unwrap().unwrap();
+    let second = decoder.next_frame().unwrap().unwrap();
+    assert!(matches!(first.decode(&Type::Boolean), Ok(TypedValue::Boolean(true))));
+    assert!(matches!(second.decode(&Type::Double), Ok(TypedValue::Double(val)) if val == 2.5));
+    assert_eq!(decoder.next_frame().unwrap(), None);
+}
+
+#[test]
+fn decodes_string_and_manifest_frames() {
+    let text = "1,4,aircraft/0/name";
+    let mut payload = (text.len() as i32).to_le_bytes().to_vec();
+    payload.extend(text.as_bytes());
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&response_bytes(-1, &payload));
+
+    let (id, value) = decoder.next_value(None).unwrap().unwrap();
+    assert_eq!(id, -1);
+    assert!(matches!(value, TypedValue::String(string) if string == text));
+}
+
+#[test]
+fn rejects_negative_length() {
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&[5, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+
+    assert!(matches!(decoder.next_frame(), Err(Error::Frame(FrameError::NegativeLength(5, -1)))));
+}
+
+#[test]
+fn encodes_requests() {
+    assert_eq!(encode_get_request(-1), vec![0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+    assert_eq!(encode_set_request(3, &TypedValue::Integer32(2)), vec![3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use ifconnect::commands::{generate_command_constants, Commands};
+use ifconnect::error::Error;
+use ifconnect::manifest::Manifest;
+use common::{connect, TIMEOUT};
+
+const MANIFEST: &str = "\
+1,2,aircraft/0/altitude_agl
+20,-1,commands/FlapsDown
+21,-1,commands/Autopilot.ToggleHDG
+22,-1,commands/ATCEntry1
+23,-1,commands/LandingLights
+";
+
+#[test]
+fn lists_commands_sorted_by_path() {
+    let commands = Commands::from_manifest(&Manifest::parse(MANIFEST).unwrap());
+
+    let names: Vec<&str> = commands.iter().map(|command| command.name()).collect();
+    assert_eq!(names, vec!["ATCEntry1", "Autopilot.ToggleHDG", "FlapsDown", "LandingLights"]);
+    assert_eq!(commands.get("FlapsDown").map(|command| command.id), Some(20));
+    assert_eq!(commands.get("commands/FlapsDown").map(|command| command.id), Some(20));
+    assert!(commands.get("aircraft/0/altitude_agl").is_none());
+}
+
+#[test]
+fn generates_constants() {
+    let source = generate_command_constants(&Manifest::parse(MANIFEST).unwrap()).unwrap();
+
+    assert!(source.contains("pub const ATC_ENTRY1: &str = \"commands/ATCEntry1\";"));
+    assert!(source.contains("pub const AUTOPILOT_TOGGLE_HDG: &str = \"commands/Autopilot.ToggleHDG\";"));
+    assert!(source.contains("pub const FLAPS_DOWN: &str = \"commands/FlapsDown\";"));
+    assert!(source.contains("pub const LANDING_LIGHTS: &str = \"commands/LandingLights\";"));
+}
+
+#[test]
+fn fails_on_colliding_constant_names() {
+    let manifest = Manifest::parse("20,-1,commands/FlapsDown\n21,-1,commands/Flaps.Down\n22,-1,commands/flaps_down\n").unwrap();
+
+    let result = generate_command_constants(&manifest);
+    assert!(matches!(result, Err(Error::ConstantCollision(name, first, second))
+        if name == "FLAPS_DOWN" && first == "commands/Flaps.Down" && second == "commands/FlapsDown"));
+}
+
+#[tokio::test]
+async fn runs_command() {
+    let (server, handle) = connect(MANIFEST).await;
+
+    let commands = handle.commands().unwrap();
+    commands.get("FlapsDown").unwrap().run(&handle).unwrap();
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+
+    assert_eq!(server.run_requests(), vec![20]);
+}
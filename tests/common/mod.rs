@@ -0,0 +1,23 @@
+//! Setup shared by the integration tests. Not every test file uses all of it.
+#![allow(dead_code)]
+
+use std::time::Duration;
+use ifconnect::connection::Connection;
+use ifconnect::handle::ConnectionHandle;
+use ifconnect::manifest::Manifest;
+use ifconnect::testing::MockServer;
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Start a mock API serving the manifest.
+pub async fn start_server(manifest: &str) -> MockServer {
+    MockServer::start(Manifest::parse(manifest).unwrap()).await.unwrap()
+}
+
+/// Start a mock API and connect to it in memory, with the manifest already fetched.
+pub async fn connect(manifest: &str) -> (MockServer, ConnectionHandle) {
+    let server = start_server(manifest).await;
+    let handle = Connection::spawn_transport(server.connect_in_memory());
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+    (server, handle)
+}
@@ -0,0 +1,112 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::time::Duration;
+use ifconnect::connection::Connection;
+use ifconnect::error::Error;
+use ifconnect::typed_value::{Type, TypedValue};
+use common::{connect, start_server, TIMEOUT};
+
+const MANIFEST: &str = "1,1,aircraft/0/systems/flaps/state\n2,4,aircraft/0/name\n3,3,aircraft/0/altitude_msl\n4,-1,commands/FlapsDown\n";
+
+#[tokio::test]
+async fn resolves_requests_for_the_same_id_in_order() {
+    let (server, handle) = connect(MANIFEST).await;
+    let values = vec![TypedValue::Integer32(1), TypedValue::Integer32(2), TypedValue::Integer32(3)];
+    server.script_state("aircraft/0/systems/flaps/state", values.clone()).unwrap();
+
+    let (first, second, third) = tokio::join!(
+        handle.get_value("aircraft/0/systems/flaps/state", TIMEOUT),
+        handle.get_value("aircraft/0/systems/flaps/state", TIMEOUT),
+        handle.get_value("aircraft/0/systems/flaps/state", TIMEOUT),
+    );
+
+    assert_eq!(vec![first.unwrap(), second.unwrap(), third.unwrap()], values);
+}
+
+#[tokio::test]
+async fn timed_out_request_doesnt_take_later_responses() {
+    let (server, handle) = connect(MANIFEST).await;
+
+    // the mock server never answers states without a value
+    let result = handle.get_value("aircraft/0/name", Duration::from_millis(50)).await;
+    assert!(matches!(result, Err(Error::Timeout(2))));
+
+    server.set_state("aircraft/0/name", TypedValue::String("A220".to_string())).unwrap();
+    let name = handle.get_value("aircraft/0/name", TIMEOUT).await.unwrap();
+    assert_eq!(name, TypedValue::String("A220".to_string()));
+}
+
+#[tokio::test]
+async fn rejects_sets_of_the_wrong_type() {
+    let (server, handle) = connect(MANIFEST).await;
+
+    let result = handle.set("aircraft/0/name", TypedValue::Integer32(1));
+    assert!(matches!(result, Err(Error::TypeMismatch(2, Type::String, Type::Integer32))));
+    let result = handle.set("aircraft/0/altitude_msl", TypedValue::Integer32(1000));
+    assert!(matches!(result, Err(Error::TypeMismatch(3, Type::Double, Type::Integer32))));
+
+    handle.set("aircraft/0/systems/flaps/state", TypedValue::Integer32(2)).unwrap();
+    handle.get_value("aircraft/0/systems/flaps/state", TIMEOUT).await.unwrap();
+    assert_eq!(server.set_requests(), vec![(1, TypedValue::Integer32(2))]);
+}
+
+#[tokio::test]
+async fn rejects_sets_of_commands() {
+    let (server, handle) = connect(MANIFEST).await;
+
+    let result = handle.set("commands/FlapsDown", TypedValue::Boolean(true));
+    assert!(matches!(result, Err(Error::NotSettable(4))));
+    let result = handle.set_id(4, TypedValue::Boolean(true));
+    assert!(matches!(result, Err(Error::NotSettable(4))));
+    assert!(server.set_requests().is_empty());
+}
+
+#[tokio::test]
+async fn coerces_set_values_when_enabled() {
+    let (server, handle) = connect(MANIFEST).await;
+    handle.set_value_coercion(true);
+
+    handle.set("aircraft/0/altitude_msl", TypedValue::Integer32(1000)).unwrap();
+    let altitude = handle.get_value("aircraft/0/altitude_msl", TIMEOUT).await.unwrap();
+    assert_eq!(altitude, TypedValue::Double(1000.0));
+    assert_eq!(server.set_requests(), vec![(3, TypedValue::Double(1000.0))]);
+
+    // only conversions without loss are made
+    let result = handle.set("aircraft/0/systems/flaps/state", TypedValue::Double(1.0));
+    assert!(matches!(result, Err(Error::TypeMismatch(1, Type::Integer32, Type::Double))));
+
+    handle.set_value_coercion(false);
+    let result = handle.set("aircraft/0/altitude_msl", TypedValue::Integer32(1000));
+    assert!(matches!(result, Err(Error::TypeMismatch(3, Type::Double, Type::Integer32))));
+}
+
+#[tokio::test]
+async fn callbacks_may_use_the_handle() {
+    let server = start_server(MANIFEST).await;
+    server.set_state("aircraft/0/systems/flaps/state", TypedValue::Integer32(3)).unwrap();
+    let handle = Connection::spawn_transport(server.connect_in_memory());
+
+    let (sender, mut subscriptions) = tokio::sync::mpsc::unbounded_channel();
+    let callback_handle = handle.clone();
+    handle.on_receive_manifest(Some(move |_| {
+        sender.send(callback_handle.subscribe("aircraft/0/systems/flaps/state", Duration::from_millis(20))).unwrap();
+    }));
+    let (sender, mut values) = tokio::sync::mpsc::unbounded_channel();
+    let callback_handle = handle.clone();
+    handle.on_receive_data(Some(move |args: ifconnect::event_args::ReceivedDataArgs| {
+        let entries = callback_handle.manifest().unwrap().get_number_of_entries();
+        let _ = sender.send((args.command_id, args.data, entries));
+    }));
+
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+    let mut subscription = tokio::time::timeout(TIMEOUT, subscriptions.recv()).await.unwrap().unwrap().unwrap();
+    assert_eq!(tokio::time::timeout(TIMEOUT, subscription.recv()).await.unwrap(), Some(TypedValue::Integer32(3)));
+    let value = tokio::time::timeout(TIMEOUT, values.recv()).await.unwrap().unwrap();
+    assert_eq!(value, (1, TypedValue::Integer32(3), 4));
+
+    // the callbacks hold handles, unregister them so the connection can stop
+    handle.on_receive_manifest(None::<fn(ifconnect::event_args::ReceivedManifestArgs)>);
+    handle.on_receive_data(None::<fn(ifconnect::event_args::ReceivedDataArgs)>);
+}
@@ -0,0 +1,42 @@
+//! Builds the library alone with every supported combination of features, like CI does.
+//! The tests and `clippy --all-targets` pull in tokio with every feature as a dev-dependency,
+//! so only a plain library build shows a feature missing from a set.
+//! Slow, so it only runs when asked for: `cargo test --test features -- --ignored`.
+
+use std::path::Path;
+use std::process::Command;
+
+// keep in sync with the matrix in .github/workflows/ci.yml
+const FEATURE_SETS: &[&str] = &[
+    "",
+    "async",
+    "blocking",
+    "discovery",
+    "serde",
+    "derive",
+    "regex",
+    "async,blocking",
+    "async,serde",
+    "async,derive",
+    "blocking,serde",
+    "discovery,serde",
+    "async,blocking,discovery,serde,derive,regex",
+];
+
+#[test]
+#[ignore]
+fn every_feature_combination_builds() {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    // a separate target directory, so the outer build isn't locked or invalidated
+    let target_dir = Path::new(manifest_dir).join("target").join("features");
+
+    for features in FEATURE_SETS {
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--lib", "--no-default-features", "--features", features])
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .current_dir(manifest_dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "the library failed to build with features {:?}", features);
+    }
+}
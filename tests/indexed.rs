@@ -0,0 +1,58 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use ifconnect::error::Error;
+use ifconnect::manifest::Manifest;
+use ifconnect::typed_value::TypedValue;
+use common::{connect, TIMEOUT};
+
+const MANIFEST: &str = "\
+12,2,aircraft/0/systems/engines/10/n1
+10,2,aircraft/0/systems/engines/0/n1
+11,2,aircraft/0/systems/engines/1/n1
+13,2,aircraft/0/systems/engines/0/n2
+20,2,aircraft/0/fuel/tanks/0/quantity
+21,2,aircraft/0/fuel/tanks/1/quantity
+1,4,aircraft/0/name
+";
+
+#[test]
+fn reports_array_cardinality() {
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+
+    assert_eq!(manifest.arrays(), vec![
+        ("aircraft", 1),
+        ("aircraft/0/fuel/tanks", 2),
+        ("aircraft/0/systems/engines", 3),
+    ]);
+    assert_eq!(manifest.node("aircraft/0/systems/engines").unwrap().indices(), vec![0, 1, 10]);
+}
+
+#[test]
+fn resolves_indexed_group() {
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+
+    let n1 = manifest.indexed_group("aircraft/0/systems/engines/{}/n1").unwrap();
+    assert_eq!(n1.len(), 3);
+    assert_eq!(n1.indices(), vec![0, 1, 10]);
+    assert_eq!(n1.state_ids(), vec![10, 11, 12]);
+    assert_eq!(n1.get(1).map(|entry| entry.id), Some(11));
+
+    assert_eq!(manifest.indexed_group("aircraft/0/systems/engines/{}/n2").unwrap().len(), 1);
+    assert!(matches!(manifest.indexed_group("aircraft/0/systems/engines/{}/egt"), Err(Error::NoSuchEntryPath(_))));
+    assert!(matches!(manifest.indexed_group("aircraft/0/name"), Err(Error::NoSuchEntryPath(_))));
+}
+
+#[tokio::test]
+async fn fetches_indexed_values() {
+    let (server, handle) = connect(MANIFEST).await;
+    server.set_state_id(10, TypedValue::Float(20.5));
+    server.set_state_id(11, TypedValue::Float(21.0));
+    server.set_state_id(12, TypedValue::Float(22.5));
+
+    let values = handle.get_indexed_values("aircraft/0/systems/engines/{}/n1", TIMEOUT).await.unwrap();
+
+    let n1: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    assert_eq!(n1, vec!["20.5", "21", "22.5"]);
+}
@@ -0,0 +1,120 @@
+use ifconnect::error::ManifestParseError;
+use ifconnect::manifest::Manifest;
+#[cfg(feature = "regex")]
+use ifconnect::manifest::Regex;
+
+const MANIFEST: &str = "1,4,aircraft/0/name\n2,0,aircraft/0/systems/lights,landing\r\n3,9,aircraft/0/future_state\n";
+
+#[test]
+fn keeps_commas_in_paths() {
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+
+    assert_eq!(manifest.get_number_of_entries(), 3);
+    assert_eq!(manifest.get_entries()[1].string, "aircraft/0/systems/lights,landing");
+}
+
+#[test]
+fn reports_offending_line() {
+    let error = Manifest::parse("1,4,aircraft/0/name\n\nx,1,aircraft/0/heading\n").unwrap_err();
+
+    assert_eq!(error, ManifestParseError::InvalidId(3, String::from("x,1,aircraft/0/heading")));
+    assert_eq!(error.line_number(), 3);
+
+    let error = Manifest::parse("1,4\n").unwrap_err();
+    assert!(matches!(error, ManifestParseError::MissingField(1, _)));
+}
+
+#[test]
+fn skips_unknown_data_types_with_warnings() {
+    let (manifest, warnings) = Manifest::parse_known_types(MANIFEST).unwrap();
+
+    assert_eq!(manifest.get_number_of_entries(), 2);
+    assert_eq!(warnings, vec![ManifestParseError::UnknownDataType(3, String::from("3,9,aircraft/0/future_state"))]);
+}
+
+#[test]
+fn round_trips_through_to_string() {
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+    let serialized = manifest.to_string();
+
+    assert_eq!(Manifest::parse(&serialized).unwrap(), manifest);
+}
+
+const TREE: &str = "\
+5,0,aircraft/0/systems/lights/landing
+3,1,aircraft/0/systems/flaps/state
+4,1,aircraft/0/systems/spoilers/state
+1,4,aircraft/0/name
+2,3,aircraft/0/latitude
+6,-1,commands/FlapsDown
+7,4,aircraft/0/namesake
+";
+
+#[test]
+fn navigates_path_tree() {
+    let manifest = Manifest::parse(TREE).unwrap();
+
+    let roots: Vec<&str> = manifest.root().children().iter().map(|node| node.name()).collect();
+    assert_eq!(roots, vec!["aircraft", "commands"]);
+
+    let aircraft = manifest.node("aircraft/0/").unwrap();
+    let children: Vec<&str> = aircraft.children().iter().map(|node| node.path()).collect();
+    assert_eq!(children, vec!["aircraft/0/latitude", "aircraft/0/name", "aircraft/0/namesake", "aircraft/0/systems"]);
+
+    let name = manifest.node("aircraft/0/name").unwrap();
+    assert_eq!(name.entry().map(|entry| entry.id), Some(1));
+    assert!(name.children().is_empty());
+    assert_eq!(name.entries().len(), 1);
+
+    let systems = manifest.node("aircraft/0/systems").unwrap();
+    assert!(systems.entry().is_none());
+    let ids: Vec<i32> = systems.entries().iter().map(|entry| entry.id).collect();
+    assert_eq!(ids, vec![3, 5, 4]);
+
+    assert!(manifest.node("aircraft/0/nam").is_none());
+}
+
+#[test]
+fn queries_sorted_by_path() {
+    let manifest = Manifest::parse(TREE).unwrap();
+    let ids = |entries: Vec<&ifconnect::manifest::Entry>| entries.iter().map(|entry| entry.id).collect::<Vec<i32>>();
+
+    assert_eq!(ids(manifest.find_glob("aircraft/0/systems/*/state")), vec![3, 4]);
+    assert_eq!(ids(manifest.find_glob("aircraft/**/landing")), vec![5]);
+    assert_eq!(ids(manifest.find_glob("aircraft/?/name*")), vec![1, 7]);
+    assert_eq!(ids(manifest.get_entries_with_prefix("aircraft/0/systems/")), vec![3, 5, 4]);
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn finds_by_regex_sorted_by_path() {
+    let manifest = Manifest::parse(TREE).unwrap();
+    let ids: Vec<i32> = manifest.find_regex(&Regex::new(r"^aircraft/0/(name|latitude)$").unwrap()).iter().map(|entry| entry.id).collect();
+
+    assert_eq!(ids, vec![2, 1]);
+}
+
+#[test]
+fn diffs_by_path() {
+    let old = Manifest::parse("1,4,aircraft/0/name\n2,1,aircraft/0/flaps\n3,2,aircraft/0/altitude_agl\n4,0,aircraft/0/gear\n").unwrap();
+    let new = Manifest::parse("1,4,aircraft/0/name\n5,1,aircraft/0/flaps\n3,3,aircraft/0/altitude_agl\n6,2,aircraft/0/altitude_msl\n").unwrap();
+
+    let diff = old.diff(&new);
+    let paths = |entries: &[ifconnect::manifest::Entry]| entries.iter().map(|entry| entry.string.clone()).collect::<Vec<_>>();
+    assert_eq!(paths(&diff.added), vec!["aircraft/0/altitude_msl"]);
+    assert_eq!(paths(&diff.removed), vec!["aircraft/0/gear"]);
+    assert_eq!(diff.retyped.len(), 1);
+    assert_eq!((diff.retyped[0].old.data_type, diff.retyped[0].new.data_type), (2, 3));
+    assert_eq!(diff.renumbered.len(), 1);
+    assert_eq!((diff.renumbered[0].old.id, diff.renumbered[0].new.id), (2, 5));
+    assert!(diff.is_breaking());
+    assert_eq!(diff.to_string().lines().collect::<Vec<_>>(), vec![
+        "+ aircraft/0/altitude_msl (id 6, type 2)",
+        "- aircraft/0/gear (id 4, type 0)",
+        "~ aircraft/0/altitude_agl: type 2 -> 3",
+        "# aircraft/0/flaps: id 2 -> 5",
+    ]);
+
+    assert!(old.diff(&old).is_empty());
+    assert!(!old.diff(&Manifest::parse("1,4,aircraft/0/name\n2,1,aircraft/0/flaps\n3,2,aircraft/0/altitude_agl\n4,0,aircraft/0/gear\n7,0,aircraft/0/new\n").unwrap()).is_breaking());
+}
@@ -0,0 +1,75 @@
+#[cfg(feature = "discovery")]
+mod common;
+
+#[cfg(feature = "discovery")]
+use std::time::Duration;
+#[cfg(feature = "discovery")]
+use ifconnect::connection::Connection;
+use ifconnect::manifest::Manifest;
+use ifconnect::manifest_cache::{ManifestCache, ManifestCacheKey};
+#[cfg(feature = "discovery")]
+use common::{start_server, TIMEOUT};
+
+#[cfg(feature = "discovery")]
+const STALE: &str = "1,2,aircraft/0/altitude_agl\n";
+const LIVE: &str = "7,2,aircraft/0/altitude_agl\n8,2,aircraft/0/altitude_msl\n";
+
+fn cache_dir(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ifconnect-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn stores_per_version_and_aircraft() {
+    let dir = cache_dir("store");
+    let cache = ManifestCache::new(&dir);
+    let key = ManifestCacheKey::new("24.1", "Airbus A320/Neo");
+    let other = ManifestCacheKey::new("24.2", "Airbus A320/Neo");
+
+    assert!(cache.load(&key).is_none());
+    cache.store(&key, &Manifest::parse(LIVE).unwrap()).unwrap();
+    assert_eq!(cache.load(&key), Some(Manifest::parse(LIVE).unwrap()));
+    assert!(cache.load(&other).is_none());
+
+    cache.remove(&key).unwrap();
+    assert!(cache.load(&key).is_none());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn keeps_similar_keys_apart() {
+    let dir = cache_dir("similar");
+    let cache = ManifestCache::new(&dir);
+    let keys = [
+        ManifestCacheKey::new("24.1", "A-320"),
+        ManifestCacheKey::new("24.1", "A 320"),
+        ManifestCacheKey::new("24.1", "A_320"),
+        ManifestCacheKey::new("24.1-A", "320"),
+    ];
+
+    cache.store(&keys[0], &Manifest::parse(LIVE).unwrap()).unwrap();
+    for key in &keys[1..] {
+        assert!(cache.load(key).is_none(), "{:?}", key);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "discovery")]
+#[tokio::test]
+async fn revalidates_cached_manifest() {
+    let dir = cache_dir("revalidate");
+    let cache = ManifestCache::new(&dir);
+    let server = start_server(LIVE).await;
+    let instance = server.instance_information();
+    let key = ManifestCacheKey::from(&instance);
+    cache.store(&key, &Manifest::parse(STALE).unwrap()).unwrap();
+
+    let handle = Connection::spawn(server.local_addr()).await.unwrap();
+    assert!(handle.use_manifest_cache(cache.clone(), &instance).unwrap());
+    // paths resolve before anything has been received
+    assert!(handle.subscribe("aircraft/0/altitude_agl", Duration::from_secs(60)).is_ok());
+
+    let live = handle.fetch_manifest(TIMEOUT).await.unwrap();
+    assert_eq!(live, Manifest::parse(LIVE).unwrap());
+    assert_eq!(cache.load(&key), Some(live));
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+#[cfg(feature = "discovery")]
+use std::time::Duration;
+#[cfg(feature = "discovery")]
+use futures::StreamExt;
+use ifconnect::connection::Connection;
+#[cfg(feature = "discovery")]
+use ifconnect::discovery::{discover, DiscoveryEvent, DiscoveryOptions};
+use ifconnect::testing::RecordedRequest;
+use ifconnect::typed_value::TypedValue;
+use common::{start_server, TIMEOUT};
+
+const MANIFEST: &str = "1,3,aircraft/0/heading_magnetic\n2,4,aircraft/0/name\n3,0,aircraft/0/systems/parking_brake\n4,-1,commands/FlapsDown\n";
+
+#[tokio::test]
+async fn serves_manifest_and_states() {
+    let server = start_server(MANIFEST).await;
+    server.set_state("aircraft/0/name", TypedValue::String("A320".to_string())).unwrap();
+    server.script_state("aircraft/0/heading_magnetic", vec![TypedValue::Double(1.0), TypedValue::Double(2.0)]).unwrap();
+
+    let handle = Connection::spawn(server.local_addr()).await.unwrap();
+    let manifest = handle.fetch_manifest(TIMEOUT).await.unwrap();
+    assert_eq!(manifest, *server.manifest());
+
+    let name = handle.get_value("aircraft/0/name", TIMEOUT).await.unwrap();
+    assert!(matches!(name, TypedValue::String(name) if name == "A320"));
+
+    let mut headings = Vec::new();
+    for _ in 0..3 {
+        match handle.get_value("aircraft/0/heading_magnetic", TIMEOUT).await.unwrap() {
+            TypedValue::Double(heading) => headings.push(heading),
+            other => panic!("unexpected value {:?}", other),
+        }
+    }
+    assert_eq!(headings, vec![1.0, 2.0, 2.0]);
+}
+
+#[tokio::test]
+async fn records_set_and_run_requests() {
+    let server = start_server(MANIFEST).await;
+    let handle = Connection::spawn(server.local_addr()).await.unwrap();
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+    server.take_requests();
+
+    handle.set("aircraft/0/systems/parking_brake", TypedValue::Boolean(true)).unwrap();
+    handle.run("commands/FlapsDown").unwrap();
+    // requests are served in order, so the others have been handled once this is answered
+    let brake = handle.get_value("aircraft/0/systems/parking_brake", TIMEOUT).await.unwrap();
+
+    assert!(matches!(brake, TypedValue::Boolean(true)));
+    assert!(matches!(server.set_requests().as_slice(), [(3, TypedValue::Boolean(true))]));
+    assert_eq!(server.run_requests(), vec![4]);
+    assert!(matches!(server.requests().last(), Some(RecordedRequest::Get(3))));
+}
+
+#[cfg(feature = "discovery")]
+#[tokio::test]
+async fn broadcasts_instance_for_discovery() {
+    let server = start_server(MANIFEST).await;
+    let port = 15731;
+    let options = DiscoveryOptions { port, window: Some(TIMEOUT), ..DiscoveryOptions::default() };
+    let stream = discover(options).await.unwrap();
+    futures::pin_mut!(stream);
+
+    server.broadcast_instance(port).await.unwrap();
+
+    match stream.next().await {
+        Some(Ok(DiscoveryEvent::Found(instance))) => {
+            assert_eq!(instance.device_id, server.instance_information().device_id);
+            assert_eq!(instance.port, server.local_addr().port() as u32);
+        },
+        other => panic!("unexpected discovery event {:?}", other),
+    }
+}
+
+#[cfg(feature = "discovery")]
+#[tokio::test]
+async fn reports_repeated_broadcasts_once() {
+    let server = start_server(MANIFEST).await;
+    let port = 15732;
+    let options = DiscoveryOptions { port, window: Some(Duration::from_millis(500)), ..DiscoveryOptions::default() };
+    let stream = discover(options).await.unwrap();
+
+    for _ in 0..3 {
+        server.broadcast_instance(port).await.unwrap();
+    }
+
+    // the stream ends with the window
+    let events: Vec<DiscoveryEvent> = tokio::time::timeout(TIMEOUT, stream.map(Result::unwrap).collect()).await.unwrap();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert!(matches!(&events[0], DiscoveryEvent::Found(instance) if instance.device_id == server.instance_information().device_id));
+}
+
+#[cfg(feature = "discovery")]
+#[tokio::test]
+async fn reports_silent_instances_as_lost() {
+    let server = start_server(MANIFEST).await;
+    let port = 15733;
+    let options = DiscoveryOptions { port, window: Some(TIMEOUT), lost_after: Duration::from_millis(100) };
+    let stream = discover(options).await.unwrap();
+    futures::pin_mut!(stream);
+    let device_id = server.instance_information().device_id;
+
+    server.broadcast_instance(port).await.unwrap();
+    let event = tokio::time::timeout(TIMEOUT, stream.next()).await.unwrap();
+    assert!(matches!(event, Some(Ok(DiscoveryEvent::Found(instance))) if instance.device_id == device_id));
+    let event = tokio::time::timeout(TIMEOUT, stream.next()).await.unwrap();
+    assert!(matches!(event, Some(Ok(DiscoveryEvent::Lost(instance))) if instance.device_id == device_id));
+
+    // an instance broadcasting again after being lost is found again
+    server.broadcast_instance(port).await.unwrap();
+    let event = tokio::time::timeout(TIMEOUT, stream.next()).await.unwrap();
+    assert!(matches!(event, Some(Ok(DiscoveryEvent::Found(instance))) if instance.device_id == device_id));
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "serde")]
+
+use ifconnect::manifest::{Entry, Manifest};
+use ifconnect::typed_value::{Type, TypedValue, Untagged};
+
+#[test]
+fn round_trips_values_through_json() {
+    let json = serde_json::to_string(&TypedValue::Double(1.5)).unwrap();
+    assert_eq!(json, r#"{"Double":1.5}"#);
+
+    let value: TypedValue = serde_json::from_str(r#"{"String":"N123"}"#).unwrap();
+    assert!(matches!(value, TypedValue::String(value) if value == "N123"));
+
+    assert_eq!(serde_json::to_string(&Type::Long).unwrap(), r#""Long""#);
+    assert_eq!(serde_json::from_str::<Type>(r#""Float""#).unwrap(), Type::Float);
+}
+
+#[test]
+fn serializes_untagged_values() {
+    let values = vec![
+        Untagged(TypedValue::Boolean(true)),
+        Untagged(TypedValue::Integer32(-3)),
+        Untagged(TypedValue::Double(2.5)),
+        Untagged(TypedValue::String("A320".to_string())),
+        Untagged(TypedValue::Long(1 << 40)),
+    ];
+    let json = serde_json::to_string(&values).unwrap();
+    assert_eq!(json, r#"[true,-3,2.5,"A320",1099511627776]"#);
+
+    let values: Vec<Untagged> = serde_json::from_str(&json).unwrap();
+    let types: Vec<Type> = values.iter().map(|value| value.0.get_type()).collect();
+    assert_eq!(types, vec![Type::Boolean, Type::Integer32, Type::Double, Type::String, Type::Long]);
+    assert!(serde_json::from_str::<Untagged>("[1]").is_err());
+}
+
+#[test]
+fn serializes_manifest_as_entries() {
+    let manifest = Manifest::parse("1,4,aircraft/0/name\n2,-1,commands/FlapsDown\n").unwrap();
+
+    let json = serde_json::to_value(&manifest).unwrap();
+    assert_eq!(json[1], serde_json::json!({ "id": 2, "data_type": -1, "string": "commands/FlapsDown" }));
+
+    let entries: Vec<Entry> = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(entries.len(), 2);
+    let deserialized: Manifest = serde_json::from_value(json).unwrap();
+    assert_eq!(deserialized, manifest);
+    assert_eq!(deserialized.get_entries_with_prefix("commands/").len(), 1);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn serializes_received_data() {
+    use ifconnect::event_args::ReceivedDataArgs;
+
+    let json = serde_json::to_string(&ReceivedDataArgs::new(7, TypedValue::Boolean(false))).unwrap();
+    assert_eq!(json, r#"{"command_id":7,"data":{"Boolean":false}}"#);
+
+    let args: ReceivedDataArgs = serde_json::from_str(&json).unwrap();
+    assert_eq!(args.command_id, 7);
+    assert!(matches!(args.data, TypedValue::Boolean(false)));
+}
+
+#[cfg(feature = "discovery")]
+#[test]
+fn round_trips_instance_information() {
+    let broadcast = r#"{"State":"Playing","Port":10111,"DeviceID":"abc","Aircraft":"A320","Version":"24.1","DeviceName":"iPad","Addresses":["192.168.1.2"],"Livery":"Delta"}"#;
+    let instance: ifconnect::discovery::InstanceInformation = serde_json::from_str(broadcast).unwrap();
+
+    assert_eq!(serde_json::to_string(&instance).unwrap(), broadcast);
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use ifconnect::error::Error;
+#[cfg(feature = "derive")]
+use ifconnect::manifest::Manifest;
+use ifconnect::state::{IfState, StateField};
+#[cfg(feature = "derive")]
+use ifconnect::state::StateBinding;
+use ifconnect::typed_value::{Type, TypedValue};
+use common::{connect, TIMEOUT};
+
+const MANIFEST: &str = "\
+1,4,aircraft/0/name
+2,0,aircraft/0/systems/lights/landing
+3,1,aircraft/0/systems/flaps/state
+4,2,aircraft/0/altitude_agl
+";
+
+#[cfg(feature = "derive")]
+#[derive(IfState, Debug, PartialEq)]
+struct Approach {
+    #[state(path = "aircraft/0/name")]
+    name: String,
+    #[state(path = "aircraft/0/systems/lights/landing")]
+    landing_lights: bool,
+    #[state(path = "aircraft/0/systems/flaps/state")]
+    flaps: i32,
+    #[state(path = "aircraft/0/systems/autobrakes/setting")]
+    autobrakes: Option<i32>,
+}
+
+#[cfg(feature = "derive")]
+#[derive(IfState)]
+#[allow(dead_code)]
+struct WrongType {
+    #[state(path = "aircraft/0/altitude_agl")]
+    altitude_agl: f64,
+}
+
+/// Only accepts positive flap settings.
+struct FlapsDeployed(i32);
+
+impl IfState for FlapsDeployed {
+    fn fields() -> &'static [StateField] {
+        &[StateField { name: "flaps", path: "aircraft/0/systems/flaps/state", data_type: Type::Integer32, required: true }]
+    }
+
+    fn from_values(values: Vec<Option<TypedValue>>) -> Option<Self> {
+        match values.as_slice() {
+            [Some(TypedValue::Integer32(flaps))] if *flaps > 0 => Some(FlapsDeployed(*flaps)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "derive")]
+#[tokio::test]
+async fn fetches_derived_struct() {
+    let (server, handle) = connect(MANIFEST).await;
+    server.set_state("aircraft/0/name", TypedValue::String("B737".to_string())).unwrap();
+    server.set_state("aircraft/0/systems/lights/landing", TypedValue::Boolean(true)).unwrap();
+    server.set_state("aircraft/0/systems/flaps/state", TypedValue::Integer32(3)).unwrap();
+
+    let binding = handle.bind_state::<Approach>().unwrap();
+    assert_eq!(binding.state_ids(), &[Some(1), Some(2), Some(3), None]);
+
+    let approach = handle.fetch_state(&binding, TIMEOUT).await.unwrap();
+    assert_eq!(approach, Approach { name: "B737".to_string(), landing_lights: true, flaps: 3, autobrakes: None });
+}
+
+#[tokio::test]
+async fn rejects_values_the_struct_refuses() {
+    let (server, handle) = connect(MANIFEST).await;
+    server.set_state("aircraft/0/systems/flaps/state", TypedValue::Integer32(0)).unwrap();
+
+    let binding = handle.bind_state::<FlapsDeployed>().unwrap();
+
+    let result = handle.fetch_state(&binding, TIMEOUT).await;
+    assert!(matches!(result, Err(Error::InvalidState(name)) if name.ends_with("FlapsDeployed")));
+
+    server.set_state("aircraft/0/systems/flaps/state", TypedValue::Integer32(2)).unwrap();
+    let flaps = handle.fetch_state(&binding, TIMEOUT).await.unwrap();
+    assert_eq!(flaps.0, 2);
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn checks_types_when_binding() {
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+
+    let result = StateBinding::<WrongType>::bind(&manifest);
+    assert!(matches!(result, Err(Error::TypeMismatch(4, Type::Float, Type::Double))));
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn requires_non_optional_fields() {
+    let manifest = Manifest::parse("1,4,aircraft/0/name\n").unwrap();
+
+    let result = StateBinding::<Approach>::bind(&manifest);
+    assert!(matches!(result, Err(Error::NoSuchEntryPath(path)) if path == "aircraft/0/systems/lights/landing"));
+    assert!(!Approach::fields()[3].required);
+}
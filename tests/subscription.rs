@@ -0,0 +1,116 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::time::Duration;
+use ifconnect::handle::ConnectionHandle;
+use ifconnect::subscription::Subscription;
+use ifconnect::testing::{MockServer, RecordedRequest};
+use ifconnect::typed_value::TypedValue;
+use common::{connect, TIMEOUT};
+
+const MANIFEST: &str = "1,3,aircraft/0/heading_magnetic\n2,1,aircraft/0/flaps\n";
+
+async fn start() -> (MockServer, ConnectionHandle) {
+    let (server, handle) = connect(MANIFEST).await;
+    server.set_state("aircraft/0/heading_magnetic", TypedValue::Double(90.0)).unwrap();
+    server.set_state("aircraft/0/flaps", TypedValue::Integer32(1)).unwrap();
+    (server, handle)
+}
+
+/// Count the values a subscription receives for the given time.
+async fn count_values(subscription: &mut Subscription, duration: Duration) -> usize {
+    let mut count = 0;
+    let _ = tokio::time::timeout(duration, async {
+        while subscription.recv().await.is_some() {
+            count += 1;
+        }
+    }).await;
+    count
+}
+
+fn get_requests(server: &MockServer, state_id: i32) -> usize {
+    server.requests().iter().filter(|request| **request == RecordedRequest::Get(state_id)).count()
+}
+
+#[tokio::test]
+async fn shares_polls_between_subscribers() {
+    let (server, handle) = start().await;
+    let mut first = handle.subscribe("aircraft/0/heading_magnetic", Duration::from_millis(50)).unwrap();
+    let mut second = handle.subscribe("aircraft/0/heading_magnetic", Duration::from_millis(50)).unwrap();
+    assert_eq!(first.state_id(), Some(1));
+    assert_eq!(second.state_id(), Some(1));
+
+    let (first_count, second_count) = tokio::join!(
+        count_values(&mut first, Duration::from_millis(500)),
+        count_values(&mut second, Duration::from_millis(500)),
+    );
+
+    // one request per interval serves both subscribers
+    let polls = get_requests(&server, 1);
+    assert!((5..=13).contains(&polls), "polled {} times", polls);
+    assert!(first_count >= 5, "first subscriber got {} values", first_count);
+    assert!(second_count >= 5, "second subscriber got {} values", second_count);
+    assert_eq!(get_requests(&server, 2), 0);
+}
+
+#[tokio::test]
+async fn delivers_at_each_subscribers_interval() {
+    let (server, handle) = start().await;
+    let mut fast = handle.subscribe("aircraft/0/heading_magnetic", Duration::from_millis(25)).unwrap();
+    let mut slow = handle.subscribe("aircraft/0/heading_magnetic", Duration::from_millis(250)).unwrap();
+
+    let (fast_count, slow_count) = tokio::join!(
+        count_values(&mut fast, Duration::from_millis(600)),
+        count_values(&mut slow, Duration::from_millis(600)),
+    );
+
+    // the state is polled at the shortest interval, the slow subscriber skips most responses
+    assert!(get_requests(&server, 1) >= 12, "polled {} times", get_requests(&server, 1));
+    assert!(fast_count >= 12, "fast subscriber got {} values", fast_count);
+    assert!((1..=4).contains(&slow_count), "slow subscriber got {} values", slow_count);
+}
+
+#[tokio::test]
+async fn stops_polling_once_every_subscription_is_dropped() {
+    let (server, handle) = start().await;
+    let mut first = handle.subscribe_id(2, Duration::from_millis(20));
+    let mut second = handle.subscribe_id(2, Duration::from_millis(20));
+    assert!(matches!(tokio::time::timeout(TIMEOUT, first.recv()).await.unwrap(), Some(TypedValue::Integer32(1))));
+    assert!(matches!(tokio::time::timeout(TIMEOUT, second.recv()).await.unwrap(), Some(TypedValue::Integer32(1))));
+
+    // the state keeps being polled while one subscriber is left
+    drop(first);
+    server.take_requests();
+    assert!(count_values(&mut second, Duration::from_millis(200)).await >= 3);
+    assert!(get_requests(&server, 2) >= 3);
+
+    drop(second);
+    // let requests already on their way arrive
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    server.take_requests();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(get_requests(&server, 2), 0);
+}
+
+#[tokio::test]
+async fn polls_zero_interval_at_the_minimum_interval() {
+    let (server, handle) = start().await;
+    let mut subscription = handle.subscribe_id(1, Duration::ZERO);
+
+    count_values(&mut subscription, Duration::from_millis(200)).await;
+    let polls = get_requests(&server, 1);
+    assert!((1..=25).contains(&polls), "polled {} times", polls);
+}
+
+#[tokio::test]
+async fn keeps_polling_next_to_an_endless_interval() {
+    let (server, handle) = start().await;
+    let mut endless = handle.subscribe_id(1, Duration::MAX);
+    let mut regular = handle.subscribe_id(2, Duration::from_millis(20));
+
+    // the first poll of the endless subscription is answered, then it never polls again
+    assert!(tokio::time::timeout(TIMEOUT, endless.recv()).await.unwrap().is_some());
+    assert!(count_values(&mut regular, Duration::from_millis(200)).await >= 3);
+    assert_eq!(get_requests(&server, 1), 1);
+}
@@ -0,0 +1,196 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use ifconnect::connection::{Connection, ConnectionState};
+use ifconnect::event_args::ConnectionStateArgs;
+use ifconnect::handle::ReconnectOptions;
+use ifconnect::testing::MockServer;
+use ifconnect::typed_value::TypedValue;
+use tokio::net::TcpStream;
+use common::{start_server, TIMEOUT};
+
+const MANIFEST: &str = "1,4,aircraft/0/name\n2,1,aircraft/0/systems/flaps/state\n";
+
+async fn start() -> MockServer {
+    let server = start_server(MANIFEST).await;
+    server.set_state("aircraft/0/name", TypedValue::String("A321".to_string())).unwrap();
+    server
+}
+
+#[tokio::test]
+async fn spawns_over_in_memory_transport() {
+    let server = start().await;
+
+    let handle = Connection::spawn_transport(server.connect_in_memory());
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+    let name = handle.get_value("aircraft/0/name", TIMEOUT).await.unwrap();
+    assert!(matches!(name, TypedValue::String(name) if name == "A321"));
+
+    handle.set("aircraft/0/systems/flaps/state", TypedValue::Integer32(2)).unwrap();
+    let flaps = handle.get_value("aircraft/0/systems/flaps/state", TIMEOUT).await.unwrap();
+    assert!(matches!(flaps, TypedValue::Integer32(2)));
+}
+
+#[tokio::test]
+async fn flushes_buffered_transport() {
+    let server = start().await;
+
+    let handle = Connection::spawn_transport(tokio::io::BufWriter::new(server.connect_in_memory()));
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+    let name = handle.get_value("aircraft/0/name", TIMEOUT).await.unwrap();
+    assert!(matches!(name, TypedValue::String(name) if name == "A321"));
+}
+
+#[tokio::test]
+async fn updates_over_in_memory_transport() {
+    let server = start().await;
+
+    let mut connection = Connection::with_transport(server.connect_in_memory());
+    assert_eq!(connection.get_connection_state(), ConnectionState::Connected);
+    connection.get_manifest().await;
+    tokio::time::timeout(TIMEOUT, async {
+        while connection.commands().is_err() {
+            connection.update().await.unwrap();
+        }
+    }).await.unwrap();
+
+    let name = connection.get_value("aircraft/0/name", TIMEOUT);
+    let name = tokio::time::timeout(TIMEOUT, async {
+        tokio::pin!(name);
+        loop {
+            tokio::select! {
+                name = &mut name => break name,
+                result = connection.update() => result.unwrap(),
+            }
+        }
+    }).await.unwrap().unwrap();
+    assert!(matches!(name, TypedValue::String(name) if name == "A321"));
+}
+
+#[tokio::test]
+async fn reconnects_with_custom_connector() {
+    let server = start().await;
+    let address = server.local_addr();
+    let options = ReconnectOptions { initial_delay: Duration::from_millis(10), ..ReconnectOptions::default() };
+
+    let stream = TcpStream::connect(address).await.unwrap();
+    let handle = Connection::spawn_transport_with(stream, move || TcpStream::connect(address), options);
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+
+    let mut state = handle.watch_state();
+    server.disconnect_clients();
+    tokio::time::timeout(TIMEOUT, state.wait_for(|state| *state == ConnectionState::Connecting)).await.unwrap().unwrap();
+    tokio::time::timeout(TIMEOUT, state.wait_for(|state| *state == ConnectionState::Connected)).await.unwrap().unwrap();
+
+    let name = handle.get_value("aircraft/0/name", TIMEOUT).await.unwrap();
+    assert!(matches!(name, TypedValue::String(name) if name == "A321"));
+}
+
+/// Drop the link of a connection whose connector always fails, returning how often it tried to reconnect.
+async fn count_reconnect_attempts(options: ReconnectOptions) -> usize {
+    let server = start().await;
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let connector = {
+        let attempts = attempts.clone();
+        move || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            std::future::ready(Err::<TcpStream, _>(io::Error::from(io::ErrorKind::ConnectionRefused)))
+        }
+    };
+    let stream = TcpStream::connect(server.local_addr()).await.unwrap();
+    let handle = Connection::spawn_transport_with(stream, connector, options);
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+
+    let mut state = handle.watch_state();
+    server.disconnect_clients();
+    tokio::time::timeout(TIMEOUT, state.wait_for(|state| *state == ConnectionState::Disconnected)).await.unwrap().unwrap();
+    attempts.load(Ordering::SeqCst)
+}
+
+fn fast_reconnect(max_attempts: u32) -> ReconnectOptions {
+    ReconnectOptions { initial_delay: Duration::from_millis(10), max_attempts: Some(max_attempts), ..ReconnectOptions::default() }
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    assert_eq!(count_reconnect_attempts(fast_reconnect(3)).await, 3);
+}
+
+#[tokio::test]
+async fn doesnt_reconnect_with_zero_max_attempts() {
+    assert_eq!(count_reconnect_attempts(fast_reconnect(0)).await, 0);
+}
+
+#[tokio::test]
+async fn backs_off_with_any_multiplier() {
+    for multiplier in [f64::NAN, -2.0, 0.0, f64::INFINITY, f64::MAX] {
+        let options = ReconnectOptions { multiplier, max_delay: Duration::from_millis(20), ..fast_reconnect(3) };
+        assert_eq!(count_reconnect_attempts(options).await, 3, "multiplier {}", multiplier);
+    }
+
+    // a delay that would overflow is capped at the maximum delay
+    let options = ReconnectOptions { multiplier: 1e300, max_delay: Duration::MAX, ..fast_reconnect(1) };
+    assert_eq!(count_reconnect_attempts(options).await, 1);
+}
+
+#[tokio::test]
+async fn reports_state_changes_to_a_callback_using_the_handle() {
+    let server = start().await;
+    let address = server.local_addr();
+    let options = ReconnectOptions { initial_delay: Duration::from_millis(10), ..ReconnectOptions::default() };
+
+    let stream = TcpStream::connect(address).await.unwrap();
+    let handle = Connection::spawn_transport_with(stream, move || TcpStream::connect(address), options);
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+
+    let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+    let callback_handle = handle.clone();
+    handle.on_state_change(Some(move |args: ConnectionStateArgs| {
+        // the connection data must not be locked while the callback runs
+        let has_manifest = callback_handle.manifest().is_ok();
+        sender.send((args.previous_state, args.state, has_manifest)).unwrap();
+    }));
+
+    server.disconnect_clients();
+    let mut received = Vec::new();
+    while received.len() < 2 {
+        received.push(tokio::time::timeout(TIMEOUT, changes.recv()).await.unwrap().unwrap());
+    }
+    assert_eq!(received, vec![
+        (ConnectionState::Connected, ConnectionState::Connecting, true),
+        (ConnectionState::Connecting, ConnectionState::Connected, true),
+    ]);
+
+    // the callback holds a handle, unregister it so the connection can stop
+    handle.on_state_change(None::<fn(ConnectionStateArgs)>);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn spawns_over_unix_socket() {
+    let server = start().await;
+    let path = std::env::temp_dir().join(format!("ifconnect-transport-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // forward the socket to the server, like an SSH tunnel would
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    let mut upstream = server.connect_in_memory();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+    });
+
+    let options = ReconnectOptions { enabled: false, ..ReconnectOptions::default() };
+    let handle = Connection::spawn_unix(&path, options).await.unwrap();
+    handle.fetch_manifest(TIMEOUT).await.unwrap();
+    let name = handle.get_value("aircraft/0/name", TIMEOUT).await.unwrap();
+    assert!(matches!(name, TypedValue::String(name) if name == "A321"));
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,90 @@
+use ifconnect::codec::{decode_value, encode_response, encode_set_request};
+use ifconnect::error::ValueError;
+use ifconnect::typed_value::{Type, TypedValue};
+
+fn all_values() -> Vec<(TypedValue, Vec<u8>)> {
+    vec![
+        (TypedValue::Boolean(true), vec![1, 0, 0, 0]),
+        (TypedValue::Boolean(false), vec![0, 0, 0, 0]),
+        (TypedValue::Integer32(-2), vec![0xfe, 0xff, 0xff, 0xff]),
+        (TypedValue::Float(1.5), vec![0x00, 0x00, 0xc0, 0x3f]),
+        (TypedValue::Double(-2.0), vec![0, 0, 0, 0, 0, 0, 0x00, 0xc0]),
+        (TypedValue::Long(0x0102030405), vec![5, 4, 3, 2, 1, 0, 0, 0]),
+        (TypedValue::String(String::from("héllo")), vec![6, 0, 0, 0, b'h', 0xc3, 0xa9, b'l', b'l', b'o']),
+        (TypedValue::String(String::new()), vec![0, 0, 0, 0]),
+    ]
+}
+
+#[test]
+fn encodes_every_variant() {
+    for (value, bytes) in all_values() {
+        assert_eq!(value.to_bytes_vec(), bytes, "{:?}", value);
+    }
+}
+
+#[test]
+fn set_request_contains_header_and_value() {
+    for (value, bytes) in all_values() {
+        let mut expected = vec![7, 0, 0, 0, 1, 0, 0, 0];
+        expected.extend(bytes);
+
+        assert_eq!(encode_set_request(7, &value), expected, "{:?}", value);
+    }
+}
+
+#[test]
+fn encoded_responses_decode_to_the_same_value() {
+    for (value, _) in all_values() {
+        let response = encode_response(7, &value);
+        let decoded = decode_value(7, &value.get_type(), &response[8..]).unwrap();
+
+        assert_eq!(decoded, value);
+    }
+}
+
+#[test]
+fn accessors_read_compatible_variants() {
+    assert_eq!(TypedValue::Float(1.5).as_f64(), Some(1.5));
+    assert_eq!(TypedValue::Long(-3).as_f64(), Some(-3.0));
+    assert_eq!(TypedValue::Integer32(7).as_i64(), Some(7));
+    assert_eq!(TypedValue::Double(7.0).as_i64(), None);
+    assert_eq!(TypedValue::Boolean(true).as_bool(), Some(true));
+    assert_eq!(TypedValue::from("A320").as_str(), Some("A320"));
+    assert_eq!(TypedValue::String("1".to_string()).as_f64(), None);
+    assert_eq!(Type::of(&TypedValue::from(2i64)), Type::Long);
+}
+
+#[test]
+fn converts_to_primitives_like_the_accessors() {
+    assert_eq!(f64::try_from(TypedValue::from(1.5f32)), Ok(1.5));
+    assert_eq!(f64::try_from(TypedValue::Long(-3)), Ok(-3.0));
+    assert_eq!(i64::try_from(TypedValue::from(-4)), Ok(-4));
+    assert_eq!(String::try_from(TypedValue::from("N1")), Ok("N1".to_string()));
+    assert_eq!(i32::try_from(TypedValue::Long(1)), Err(ValueError::WrongType(Type::Integer32, Type::Long)));
+    assert_eq!(bool::try_from(TypedValue::Integer32(1)), Err(ValueError::WrongType(Type::Boolean, Type::Integer32)));
+    assert_eq!(i32::try_from(TypedValue::Boolean(true)), Err(ValueError::WrongType(Type::Integer32, Type::Boolean)));
+    assert_eq!(i64::try_from(TypedValue::Boolean(true)), Err(ValueError::WrongType(Type::Long, Type::Boolean)));
+    assert_eq!(f32::try_from(TypedValue::Double(1.5)), Err(ValueError::WrongType(Type::Float, Type::Double)));
+}
+
+#[test]
+fn orders_values_of_the_same_type() {
+    assert!(TypedValue::Double(1.0) < TypedValue::Double(2.0));
+    assert!(TypedValue::from("a") < TypedValue::from("b"));
+    assert_eq!(TypedValue::Integer32(1).partial_cmp(&TypedValue::Double(1.0)), None);
+    assert_ne!(TypedValue::Integer32(1), TypedValue::Long(1));
+}
+
+#[test]
+fn parses_input_for_a_type() {
+    assert_eq!(TypedValue::parse(" TRUE ", &Type::Boolean), Ok(TypedValue::Boolean(true)));
+    assert_eq!(TypedValue::parse("0", &Type::Boolean), Ok(TypedValue::Boolean(false)));
+    assert_eq!(TypedValue::parse("-12", &Type::Integer32), Ok(TypedValue::Integer32(-12)));
+    assert_eq!(TypedValue::parse("2.5", &Type::Float), Ok(TypedValue::Float(2.5)));
+    assert_eq!(TypedValue::parse("1e3", &Type::Double), Ok(TypedValue::Double(1000.0)));
+    assert_eq!(TypedValue::parse("9000000000", &Type::Long), Ok(TypedValue::Long(9_000_000_000)));
+    assert_eq!(TypedValue::parse(" N123 ", &Type::String), Ok(TypedValue::from(" N123 ")));
+
+    assert_eq!(TypedValue::parse("9000000000", &Type::Integer32), Err(ValueError::Invalid(Type::Integer32, "9000000000".to_string())));
+    assert_eq!(TypedValue::parse("yes", &Type::Boolean), Err(ValueError::Invalid(Type::Boolean, "yes".to_string())));
+}